@@ -0,0 +1,154 @@
+//! Personal notes and tags stored locally against record ids.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use chrono::Local;
+use unicode_width::UnicodeWidthStr;
+
+use crate::layout::truncate;
+use crate::theme::paint;
+use crate::{error, storage, Record, SearchResults};
+
+const STORAGE: &str = "annotations";
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Annotations {
+    #[serde(default)]
+    records: BTreeMap<String, Annotation>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Annotation {
+    #[serde(default)]
+    pub notes: Vec<String>,
+    #[serde(default)]
+    pub tags: BTreeSet<String>,
+    pub updated: String,
+    /// Copy of the record so that annotations can be listed offline
+    pub record: Record,
+}
+
+impl Annotation {
+    fn is_empty(&self) -> bool {
+        self.notes.is_empty() && self.tags.is_empty()
+    }
+
+    fn matches(&self, term: &str) -> bool {
+        if let Some(tag) = term.strip_prefix('#') {
+            return self.tags.iter().any(|t| t.to_lowercase() == tag);
+        }
+        self.notes
+            .iter()
+            .chain(self.tags.iter())
+            .chain(self.record.title.iter())
+            .chain(self.record.primary_authors.iter())
+            .any(|text| text.to_lowercase().contains(term))
+    }
+}
+
+impl Annotations {
+    pub fn get(&self, id: &str) -> Option<&Annotation> {
+        self.records.get(id)
+    }
+}
+
+pub fn load() -> Annotations {
+    storage::load(STORAGE).unwrap_or_else(|e| {
+        error(&tr!("Error loading annotations: {}", e));
+        Annotations::default()
+    })
+}
+
+/// Add a note, or add/remove tags ("-tag" removes) of a record.
+/// An empty note clears the notes of the record.
+pub fn annotate(kind: &str, rec: &Record, text: &str, annotations: &mut Annotations) {
+    let id = match &rec.id {
+        Some(id) => id.clone(),
+        None => return,
+    };
+    let annotation = annotations.records.entry(id.clone()).or_insert_with(|| Annotation {
+        notes: vec![],
+        tags: BTreeSet::new(),
+        updated: String::new(),
+        record: rec.clone(),
+    });
+    match kind {
+        "note" if text.is_empty() => annotation.notes.clear(),
+        "note" => annotation.notes.push(text.to_string()),
+        _ => edit_tags(&mut annotation.tags, text),
+    }
+    annotation.updated = Local::now().to_rfc3339();
+    annotation.record = rec.clone();
+    if annotation.is_empty() {
+        annotations.records.remove(&id);
+    }
+
+    if let Err(e) = storage::store(STORAGE, annotations) {
+        error(&tr!("Error saving annotations: {}", e));
+    }
+}
+
+/// Add the tags of `text`, or remove those starting with `-`. A leading `#`
+/// is left out, as tags are shown with one.
+fn edit_tags(tags: &mut BTreeSet<String>, text: &str) {
+    for tag in text.split(|c: char| c == ',' || c.is_whitespace()) {
+        let (remove, tag) = match tag.strip_prefix('-') {
+            Some(tag) => (true, tag),
+            None => (false, tag),
+        };
+        let tag = tag.trim_start_matches('#');
+        if tag.is_empty() {
+            continue;
+        }
+        if remove {
+            tags.remove(tag);
+        } else {
+            tags.insert(tag.to_string());
+        }
+    }
+}
+
+/// Find annotated records whose notes, tags, title or authors contain all of the
+/// query terms ("#tag" matches a tag exactly). Most recently annotated come first.
+pub fn search(query: &str, annotations: &Annotations) -> SearchResults {
+    let terms: Vec<String> = query.split_whitespace().map(|term| term.to_lowercase()).collect();
+    let mut found: Vec<&Annotation> = annotations
+        .records
+        .values()
+        .filter(|annotation| terms.iter().all(|term| annotation.matches(term)))
+        .collect();
+    found.sort_by(|a, b| b.updated.cmp(&a.updated));
+    SearchResults {
+        result_count: found.len() as i32,
+        records: found.into_iter().map(|annotation| annotation.record.clone()).collect(),
+    }
+}
+
+/// Marker line shown under an annotated search result, fitted to `width` columns.
+pub fn format_annotation(annotation: &Annotation, width: usize) -> String {
+    let tags: Vec<String> = annotation.tags.iter().map(|tag| format!("#{}", tag)).collect();
+    let tags = tags.join(" ");
+    // Indentation, marker and the spaces around tags
+    let notes_width = width.saturating_sub(7 + tags.width());
+    format!(
+        "{fill:>4}{marker} {tags} {notes}",
+        fill = "",
+        marker = paint("marker", "✎"),
+        tags = paint("tags", &tags),
+        notes = paint("notes", &truncate(&annotation.notes.join(" / "), notes_width))
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tags() {
+        let mut tags = BTreeSet::new();
+        edit_tags(&mut tags, "#x, y #z");
+        assert_eq!(tags.iter().collect::<Vec<_>>(), ["x", "y", "z"]);
+        edit_tags(&mut tags, "-#x -y #");
+        assert_eq!(tags.iter().collect::<Vec<_>>(), ["z"]);
+    }
+}
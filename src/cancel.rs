@@ -0,0 +1,80 @@
+//! Cancelling API requests with Ctrl-C, and a spinner while waiting for them.
+//!
+//! Ctrl-C during a request cancels only that request. At other times it's
+//! ignored, and at the prompt the line editor reads it as a key, so that the
+//! history and the session are saved on the way out.
+
+use std::io::{self, IsTerminal, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::time::{Duration, Instant};
+
+use crate::i18n::t;
+use crate::theme::paint_stderr;
+
+static WAITING: AtomicBool = AtomicBool::new(false);
+static CANCELLED: AtomicBool = AtomicBool::new(false);
+static SPINNER: AtomicBool = AtomicBool::new(false);
+
+const FRAMES: [char; 10] = ['⠋', '⠙', '⠹', '⠸', '⠼', '⠴', '⠦', '⠧', '⠇', '⠏'];
+const FRAME_INTERVAL: Duration = Duration::from_millis(100);
+/// Quick requests are done before the spinner appears.
+const SPINNER_DELAY: Duration = Duration::from_millis(300);
+
+/// Handle Ctrl-C, and show a spinner during requests if `spinner` is set and
+/// the output is a terminal.
+pub fn init(spinner: bool) {
+    SPINNER.store(spinner && io::stderr().is_terminal(), Ordering::SeqCst);
+    let handler = ctrlc::set_handler(|| {
+        if WAITING.load(Ordering::SeqCst) {
+            CANCELLED.store(true, Ordering::SeqCst);
+        }
+    });
+    if let Err(e) = handler {
+        crate::error(&e.to_string());
+    }
+}
+
+pub fn cancelled() -> bool {
+    CANCELLED.load(Ordering::SeqCst)
+}
+
+/// Wait for a result, showing a spinner after a moment. None if cancelled.
+pub fn wait<T>(receiver: &Receiver<T>) -> Option<T> {
+    let start = Instant::now();
+    let mut frame = 0;
+    let mut spinning = false;
+    let result = loop {
+        if cancelled() {
+            break None;
+        }
+        match receiver.recv_timeout(FRAME_INTERVAL) {
+            Ok(result) => break Some(result),
+            Err(RecvTimeoutError::Disconnected) => break None,
+            Err(RecvTimeoutError::Timeout) => {}
+        }
+        if SPINNER.load(Ordering::SeqCst) && start.elapsed() >= SPINNER_DELAY {
+            let text = format!("{} {}", FRAMES[frame % FRAMES.len()], t("Searching… (Ctrl-C cancels)"));
+            eprint!("\r{}", paint_stderr("debug", &text));
+            let _ = io::stderr().flush();
+            frame += 1;
+            spinning = true;
+        }
+    };
+    if spinning {
+        eprint!("\r\x1b[K");
+    }
+    result
+}
+
+/// Run `f` as a cancellable request.
+pub fn request<T>(f: impl FnOnce() -> Result<T, String>) -> Result<T, String> {
+    CANCELLED.store(false, Ordering::SeqCst);
+    WAITING.store(true, Ordering::SeqCst);
+    let result = f();
+    WAITING.store(false, Ordering::SeqCst);
+    if cancelled() {
+        return Err(t("Request cancelled").to_string());
+    }
+    result
+}
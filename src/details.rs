@@ -0,0 +1,149 @@
+//! Formatted detail view of a single record.
+
+use serde_json::Value;
+
+use crate::i18n::t;
+use crate::theme::paint;
+use crate::{rec_fields, Record, TranslatedString};
+
+/// Minimum width of the label column.
+const LABEL_WIDTH: usize = 12;
+
+/// Labels of the rows, translated when the record is formatted.
+const ROW_LABELS: &[&str] = &["Authors", "Year", "Formats", "Buildings", "Languages", "Series", "Physical", "Images", "Id"];
+
+/// Fields needed by the detail view in addition to the result list fields.
+pub fn detail_fields() -> Vec<String> {
+    let mut fields = rec_fields();
+    fields.extend(
+        [
+            "summary",
+            "subjects",
+            "languages",
+            "series",
+            "physicalDescriptions",
+            "onlineUrls",
+        ]
+        .iter()
+        .map(|field| field.to_string()),
+    );
+    fields
+}
+
+fn translated(values: &[TranslatedString]) -> String {
+    let mut labels: Vec<&str> = vec![];
+    for value in values {
+        if !labels.contains(&value.translated.as_str()) {
+            labels.push(&value.translated);
+        }
+    }
+    labels.join(" / ")
+}
+
+/// Series are either plain strings or objects with a name and a number.
+fn series_name(series: &Value) -> Option<String> {
+    match series {
+        Value::String(name) => Some(name.clone()),
+        Value::Object(fields) => {
+            let name = fields.get("name")?.as_str()?;
+            match fields.get("number").and_then(Value::as_str).filter(|n| !n.is_empty()) {
+                Some(number) => Some(format!("{} ; {}", name, number)),
+                None => Some(name.to_string()),
+            }
+        }
+        _ => None,
+    }
+}
+
+/// A labelled value, aligned to a label column of `label_width` if given.
+fn row(label: &str, value: &str, label_width: Option<usize>) -> Option<String> {
+    if value.is_empty() {
+        return None;
+    }
+    match label_width {
+        Some(width) => Some(format!(
+            "{label:<width$}{value}",
+            label = paint("label", label),
+            width = width,
+            value = value
+        )),
+        None => Some(format!("{}: {}", label, value)),
+    }
+}
+
+fn section(label: &str, lines: Vec<String>, labelled: bool) -> Option<String> {
+    if lines.is_empty() {
+        return None;
+    }
+    if labelled {
+        return Some(format!("{}: {}", label, lines.join("; ")));
+    }
+    let lines: Vec<String> = lines.iter().map(|line| format!("  {}", line)).collect();
+    Some(format!("\n{}\n{}", paint("heading", label), lines.join("\n")))
+}
+
+/// Format a record for display. `labelled` puts every value on a line of its
+/// own after its label, without colours or column alignment.
+pub fn format_record(rec: &Record, labelled: bool) -> String {
+    let mut authors: Vec<String> = rec.primary_authors.clone();
+    authors.extend(rec.non_presenter_authors.iter().map(|author| match &author.role {
+        Some(role) if !role.is_empty() => format!("{} ({})", author.name, role),
+        _ => author.name.clone(),
+    }));
+    let subjects: Vec<String> = rec.subjects.iter().map(|subject| subject.join(" : ")).collect();
+    let series: Vec<String> = rec.series.iter().filter_map(series_name).collect();
+    let mut summary = rec.summary.clone().unwrap_or_default();
+    summary.extend(rec.description.clone());
+    let online_urls: Vec<String> = rec
+        .online_urls
+        .iter()
+        .map(|online| {
+            let source = translated(&online.source);
+            format!(
+                "{text}{url}{source}",
+                text = if online.text.is_empty() || online.text == online.url {
+                    "".to_string()
+                } else {
+                    format!("{} ", online.text)
+                },
+                url = paint("link", &online.url),
+                source = if source.is_empty() { source } else { format!(" ({})", source) }
+            )
+        })
+        .collect();
+
+    // Screen readers read out "|"
+    let separator = if labelled { ", " } else { " | " };
+    let label_width = if labelled {
+        None
+    } else {
+        let widest = ROW_LABELS.iter().map(|label| t(label).chars().count()).max().unwrap_or(0);
+        Some(LABEL_WIDTH.max(widest + 2))
+    };
+
+    let lines: Vec<Option<String>> = vec![
+        if labelled {
+            row(t("Title"), rec.title.as_deref().unwrap_or(""), None)
+        } else {
+            Some(paint("title", rec.title.as_deref().unwrap_or("")).to_string())
+        },
+        if labelled { None } else { Some("".to_string()) },
+        row(t("Authors"), &authors.join(separator), label_width),
+        row(t("Year"), rec.year.as_deref().unwrap_or(""), label_width),
+        row(t("Formats"), &translated(&rec.formats), label_width),
+        row(t("Buildings"), &translated(&rec.buildings), label_width),
+        row(t("Languages"), &rec.languages.join(", "), label_width),
+        row(t("Series"), &series.join(separator), label_width),
+        row(t("Physical"), &rec.physical_descriptions.join(" ; "), label_width),
+        row(
+            t("Images"),
+            &if rec.images.is_empty() { "".to_string() } else { rec.images.len().to_string() },
+            label_width,
+        ),
+        row(t("Id"), rec.id.as_deref().unwrap_or(""), label_width),
+        section(t("Summary"), summary, labelled),
+        section(t("Subjects"), subjects, labelled),
+        section(t("Online"), online_urls, labelled),
+    ];
+    lines.into_iter().flatten().collect::<Vec<String>>().join("\n")
+}
@@ -0,0 +1,195 @@
+//! Search filters as supported by VuFind: `field:value`, excluded
+//! `-field:value` and `~field:value`, which is OR'ed with the other `~`
+//! filters of the same field.
+
+use std::fmt;
+use std::str::FromStr;
+
+use serde::de::{self, Deserialize, Deserializer};
+use serde::ser::{Serialize, Serializer};
+
+use crate::geo;
+use crate::i18n::t;
+use crate::Record;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operator {
+    And,
+    Not,
+    Or,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Filter {
+    pub operator: Operator,
+    pub field: String,
+    pub value: String,
+}
+
+impl FromStr for Filter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (operator, filter) = match s.chars().next() {
+            Some('-') => (Operator::Not, &s[1..]),
+            Some('~') => (Operator::Or, &s[1..]),
+            _ => (Operator::And, s),
+        };
+        match filter.split_once(':') {
+            Some((field, value)) if !field.is_empty() && !value.is_empty() => {
+                let value = value.strip_prefix('"').and_then(|v| v.strip_suffix('"')).unwrap_or(value);
+                Ok(Filter {
+                    operator,
+                    field: field.to_string(),
+                    value: value.to_string(),
+                })
+            }
+            _ => Err(tr!("Invalid filter: {} (expected field:value)", s)),
+        }
+    }
+}
+
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let prefix = match self.operator {
+            Operator::And => "",
+            Operator::Not => "-",
+            Operator::Or => "~",
+        };
+        write!(f, "{}{}:\"{}\"", prefix, self.field, self.value)
+    }
+}
+
+// Filters are stored and sent to the API in their query string form
+impl Serialize for Filter {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Filter {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?.parse().map_err(de::Error::custom)
+    }
+}
+
+/// Label of a filter field, the field name itself if it is not a common one.
+fn field_label(field: &str) -> String {
+    let label = match field {
+        "format" => "Format",
+        "building" => "Building",
+        "language" => "Language",
+        "author_facet" => "Author",
+        "topic_facet" => "Subject",
+        "genre_facet" => "Genre",
+        "geographic_facet" => "Region",
+        "era_facet" => "Era",
+        "main_date_str" => "Year",
+        "online_boolean" | "free_online_boolean" => "Online",
+        "usage_rights_str_mv" => "Usage rights",
+        "sector_str_mv" => "Sector",
+        "hierarchy_parent_title" => "Collection",
+        "location_geo" => "Location",
+        _ => return field.to_string(),
+    };
+    t(label).to_string()
+}
+
+/// Translated label of a filter value, found in the formats and buildings of
+/// the records. Other hierarchical values (`0/Book/`) are shown by their last level.
+fn value_label(value: &str, records: &[Record]) -> String {
+    if let Some(location) = geo::describe_filter(value) {
+        return location;
+    }
+    if let Some((first, last)) = year_range(value) {
+        return format!("{}–{}", first, last);
+    }
+    let translated = records
+        .iter()
+        .flat_map(|rec| rec.formats.iter().chain(rec.buildings.iter()))
+        .find(|translated| translated.value == value);
+    match translated {
+        Some(translated) => translated.translated.clone(),
+        None => match value.trim_end_matches('/').rsplit_once('/') {
+            Some((level, last)) if level.starts_with(|c: char| c.is_ascii_digit()) => last.to_string(),
+            _ => value.to_string(),
+        },
+    }
+}
+
+/// First and last year of a range value, e.g. `[1950 TO 1959]` selected with `:timeline`.
+pub fn year_range(value: &str) -> Option<(i64, i64)> {
+    let (first, last) = value.strip_prefix('[')?.strip_suffix(']')?.split_once(" TO ")?;
+    Some((first.trim().parse().ok()?, last.trim().parse().ok()?))
+}
+
+/// Readable summary of filters grouped by field, e.g.
+/// `Format: Book or Map, not Image; Building: Library`.
+pub fn format_summary(filters: &[Filter], records: &[Record]) -> String {
+    let mut fields: Vec<&str> = vec![];
+    for filter in filters {
+        if !fields.contains(&filter.field.as_str()) {
+            fields.push(&filter.field);
+        }
+    }
+    fields
+        .iter()
+        .map(|field| {
+            let values = |operator: Operator| -> Vec<String> {
+                filters
+                    .iter()
+                    .filter(|filter| filter.field == *field && filter.operator == operator)
+                    .map(|filter| value_label(&filter.value, records))
+                    .collect()
+            };
+            let mut parts = values(Operator::And);
+            let any = values(Operator::Or);
+            if !any.is_empty() {
+                parts.push(any.join(&format!(" {} ", t("or"))));
+            }
+            parts.extend(values(Operator::Not).iter().map(|value| format!("{} {}", t("not"), value)));
+            format!("{}: {}", field_label(field), parts.join(", "))
+        })
+        .collect::<Vec<String>>()
+        .join("; ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filters(filters: &[&str]) -> Vec<Filter> {
+        filters.iter().map(|filter| filter.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn parse() {
+        let filter: Filter = "~format:\"0/Book/\"".parse().unwrap();
+        assert_eq!(filter.operator, Operator::Or);
+        assert_eq!(filter.field, "format");
+        assert_eq!(filter.value, "0/Book/");
+        assert_eq!(filter.to_string(), "~format:\"0/Book/\"");
+        assert_eq!("-building:0/Helka/".parse::<Filter>().unwrap().to_string(), "-building:\"0/Helka/\"");
+        assert!("format".parse::<Filter>().is_err());
+        assert!("format:".parse::<Filter>().is_err());
+    }
+
+    #[test]
+    fn summary() {
+        let filters = filters(&["~format:0/Book/", "~format:0/Map/", "-format:0/Image/", "building:0/Helka/"]);
+        assert_eq!(
+            format_summary(&filters, &[]),
+            "Aineistotyyppi: Book tai Map, ei Image; Organisaatio: Helka"
+        );
+    }
+
+    #[test]
+    fn summary_years() {
+        let decade = filters(&["main_date_str:[1950 TO 1959]"]);
+        assert_eq!(format_summary(&decade, &[]), "Vuosi: 1950–1959");
+        assert_eq!(decade[0].to_string(), "main_date_str:\"[1950 TO 1959]\"");
+        let years = filters(&["~main_date_str:1951", "~main_date_str:1958"]);
+        assert_eq!(format_summary(&years, &[]), "Vuosi: 1951 tai 1958");
+    }
+}
@@ -0,0 +1,270 @@
+//! Spatial search filters and a Braille map of record locations.
+
+use std::str::FromStr;
+
+use serde_json::Value;
+
+use crate::filters::{Filter, Operator};
+use crate::i18n::t;
+use crate::{Params, Record};
+
+/// Solr field with the locations of records.
+const LOCATION_FIELD: &str = "location_geo";
+/// API field with the locations of a record as WKT.
+pub const LOCATION_RECORD_FIELD: &str = "geoLocations";
+const KM_PER_DEGREE: f64 = 111.195;
+/// Used with `--near` if `--radius` is not given.
+const DEFAULT_RADIUS: f64 = 10.0;
+const MAP_HEIGHT: usize = 16;
+const MAP_MAX_WIDTH: usize = 100;
+
+fn parse_coordinates(s: &str, count: usize) -> Result<Vec<f64>, String> {
+    let numbers: Result<Vec<f64>, _> = s.split(',').map(|n| n.trim().parse::<f64>()).collect();
+    match numbers {
+        Ok(numbers) if numbers.len() == count => Ok(numbers),
+        _ => Err(tr!("Invalid coordinates: {}", s)),
+    }
+}
+
+fn check_range(lat: f64, lon: f64) -> Result<(), String> {
+    if (-90.0..=90.0).contains(&lat) && (-180.0..=180.0).contains(&lon) {
+        Ok(())
+    } else {
+        Err(tr!("Coordinates out of range: {}, {}", lat, lon))
+    }
+}
+
+/// `lon1,lat1,lon2,lat2`, any two opposite corners.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BoundingBox {
+    west: f64,
+    south: f64,
+    east: f64,
+    north: f64,
+}
+impl FromStr for BoundingBox {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let c = parse_coordinates(s, 4)?;
+        check_range(c[1], c[0])?;
+        check_range(c[3], c[2])?;
+        Ok(BoundingBox {
+            west: c[0].min(c[2]),
+            south: c[1].min(c[3]),
+            east: c[0].max(c[2]),
+            north: c[1].max(c[3]),
+        })
+    }
+}
+
+/// `lat,lon`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Point {
+    lat: f64,
+    lon: f64,
+}
+impl FromStr for Point {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let c = parse_coordinates(s, 2)?;
+        check_range(c[0], c[1])?;
+        Ok(Point { lat: c[0], lon: c[1] })
+    }
+}
+
+fn location_filter(shape: String) -> Filter {
+    Filter {
+        operator: Operator::And,
+        field: LOCATION_FIELD.to_string(),
+        value: format!("Intersects({})", shape),
+    }
+}
+
+pub fn bbox_filter(bbox: &BoundingBox) -> Filter {
+    location_filter(format!(
+        "ENVELOPE({}, {}, {}, {})",
+        bbox.west, bbox.east, bbox.north, bbox.south
+    ))
+}
+
+/// Records within `radius` kilometres of a point. The radius is converted to
+/// degrees, as the index uses geographic coordinates.
+pub fn near_filter(point: &Point, radius: f64) -> Filter {
+    location_filter(format!(
+        "BUFFER(POINT({} {}), {:.4})",
+        point.lon,
+        point.lat,
+        radius / KM_PER_DEGREE
+    ))
+}
+
+/// Readable form of a location filter value made by `bbox_filter` or `near_filter`.
+pub fn describe_filter(value: &str) -> Option<String> {
+    let shape = value.strip_prefix("Intersects(")?.strip_suffix(')')?;
+    if let Some(corners) = shape.strip_prefix("ENVELOPE(").and_then(|s| s.strip_suffix(')')) {
+        let c = parse_coordinates(corners, 4).ok()?;
+        return Some(format!("{} – {}", format_location(c[0], c[2]), format_location(c[1], c[3])));
+    }
+    let (point, distance) = shape.strip_prefix("BUFFER(POINT(")?.strip_suffix(')')?.split_once("),")?;
+    let c: Vec<f64> = point.split_whitespace().filter_map(|n| n.parse().ok()).collect();
+    let distance: f64 = distance.trim().parse().ok()?;
+    match c[..] {
+        [lon, lat] => Some(tr!(
+            "within {} km of {}",
+            format!("{:.1}", distance * KM_PER_DEGREE),
+            format_location(lon, lat)
+        )),
+        _ => None,
+    }
+}
+
+/// Replace the `--bbox`, `--near` and `--radius` options with location filters.
+pub fn add_location_filters(params: &mut Params) {
+    let mut filters = vec![];
+    if let Some(bbox) = params.bbox.take() {
+        filters.push(bbox_filter(&bbox));
+    }
+    if let Some(point) = params.near.take() {
+        filters.push(near_filter(&point, params.radius.unwrap_or(DEFAULT_RADIUS)));
+    }
+    params.radius = None;
+    if !filters.is_empty() {
+        params.filter.get_or_insert_with(Vec::new).extend(filters);
+    }
+}
+
+/// Centre of a WKT shape (`POINT`, `ENVELOPE`, `POLYGON` etc.) as (lon, lat).
+fn shape_centre(wkt: &str) -> Option<(f64, f64)> {
+    let (kind, coordinates) = wkt.split_once('(')?;
+    let numbers = |s: &str| -> Vec<f64> {
+        s.split_whitespace().filter_map(|n| n.parse().ok()).collect()
+    };
+    if kind.trim().eq_ignore_ascii_case("ENVELOPE") {
+        // ENVELOPE(minX, maxX, maxY, minY)
+        let c = numbers(&coordinates.replace([',', ')'], " "));
+        return match c[..] {
+            [west, east, north, south] => Some(((west + east) / 2.0, (north + south) / 2.0)),
+            _ => None,
+        };
+    }
+    let points: Vec<Vec<f64>> = coordinates
+        .split([',', '(', ')'])
+        .map(numbers)
+        .filter(|point| point.len() == 2)
+        .collect();
+    if points.is_empty() {
+        return None;
+    }
+    let count = points.len() as f64;
+    Some((
+        points.iter().map(|point| point[0]).sum::<f64>() / count,
+        points.iter().map(|point| point[1]).sum::<f64>() / count,
+    ))
+}
+
+/// Location of a record as (lon, lat), the centre of its first location.
+pub fn record_location(rec: &Record) -> Option<(f64, f64)> {
+    let locations = rec.extra.get(LOCATION_RECORD_FIELD)?;
+    let first = match locations {
+        Value::Array(locations) => locations.first()?,
+        location => location,
+    };
+    shape_centre(first.as_str()?)
+}
+
+pub fn has_locations(records: &[Record]) -> bool {
+    records.iter().any(|rec| rec.extra.contains_key(LOCATION_RECORD_FIELD))
+}
+
+/// Braille dot bits of a character cell, by row and column.
+const DOTS: [[u32; 2]; 4] = [[0x01, 0x08], [0x02, 0x10], [0x04, 0x20], [0x40, 0x80]];
+
+fn format_location(lon: f64, lat: f64) -> String {
+    format!(
+        "{:.2}°{} {:.2}°{}",
+        lat.abs(),
+        if lat < 0.0 { "S" } else { "N" },
+        lon.abs(),
+        if lon < 0.0 { "W" } else { "E" }
+    )
+}
+
+/// Draw the locations of records as Braille dots, labelled with their numbers
+/// on the page, in a frame `width` columns wide, followed by a list of the
+/// locations. `list_only` leaves out the map.
+pub fn format_map(records: &[Record], width: usize, list_only: bool) -> String {
+    let located: Vec<(usize, (f64, f64))> = records
+        .iter()
+        .enumerate()
+        .filter_map(|(i, rec)| record_location(rec).map(|location| (i + 1, location)))
+        .collect();
+    if located.is_empty() {
+        return t("No locations on this page").to_string();
+    }
+    let mut legend: Vec<String> = located
+        .iter()
+        .map(|(num, (lon, lat))| {
+            let title = records[num - 1].title.clone().unwrap_or_default();
+            format!("{:>3} {}  {}", num, format_location(*lon, *lat), title)
+        })
+        .collect();
+    legend.push(tr!("{} of {} records have a location", located.len(), records.len()));
+    if list_only {
+        return legend.join("\n");
+    }
+
+    let columns = width.saturating_sub(2).clamp(10, MAP_MAX_WIDTH);
+    let rows = MAP_HEIGHT;
+    let lons = located.iter().map(|(_, (lon, _))| *lon);
+    let lats = located.iter().map(|(_, (_, lat))| *lat);
+    let (mut west, mut east) = (lons.clone().fold(f64::MAX, f64::min), lons.fold(f64::MIN, f64::max));
+    let (mut south, mut north) = (lats.clone().fold(f64::MAX, f64::min), lats.fold(f64::MIN, f64::max));
+    // Some room around the outermost locations, and some area around a single one
+    let margin = ((east - west).max(north - south) * 0.1).max(0.05);
+    west -= margin;
+    east += margin;
+    south -= margin;
+    north += margin;
+
+    // Degrees of longitude shrink towards the poles
+    let lon_scale = ((north + south) / 2.0).to_radians().cos();
+    let (dots_x, dots_y) = (columns * 2, rows * 4);
+    let scale = (dots_x as f64 / ((east - west) * lon_scale)).min(dots_y as f64 / (north - south));
+    let offset_x = (dots_x as f64 - (east - west) * lon_scale * scale) / 2.0;
+    let offset_y = (dots_y as f64 - (north - south) * scale) / 2.0;
+
+    let mut cells = vec![vec![0u32; columns]; rows];
+    let mut labels: Vec<Vec<Option<char>>> = vec![vec![None; columns]; rows];
+    for (num, (lon, lat)) in &located {
+        let x = (((lon - west) * lon_scale * scale + offset_x) as usize).min(dots_x - 1);
+        let y = (((north - lat) * scale + offset_y) as usize).min(dots_y - 1);
+        cells[y / 4][x / 2] |= DOTS[y % 4][x % 2];
+        let (row, column) = (y / 4, x / 2 + 1);
+        let label: Vec<char> = num.to_string().chars().collect();
+        if column + label.len() <= columns && labels[row][column..column + label.len()].iter().all(Option::is_none) {
+            for (i, c) in label.into_iter().enumerate() {
+                labels[row][column + i] = Some(c);
+            }
+        }
+    }
+
+    let mut lines = vec![format!("┌{}┐", "─".repeat(columns))];
+    for (cells, labels) in cells.iter().zip(labels.iter()) {
+        let line: String = cells
+            .iter()
+            .zip(labels.iter())
+            .map(|(dots, label)| match label {
+                Some(c) if *dots == 0 => *c,
+                _ => std::char::from_u32(0x2800 + dots).unwrap_or(' '),
+            })
+            .collect();
+        lines.push(format!("│{}│", line));
+    }
+    lines.push(format!("└{}┘", "─".repeat(columns)));
+    lines.push(format!("{} – {}", format_location(west, north), format_location(east, south)));
+    lines.push("".to_string());
+    lines.extend(legend);
+    lines.join("\n")
+}
@@ -0,0 +1,305 @@
+//! Harvesting every record of a search to a JSON Lines file, with a checkpoint
+//! file to resume an interrupted harvest.
+
+use std::fs::{self, OpenOptions};
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use serde_json::Value;
+use structopt::StructOpt;
+
+use crate::i18n::t;
+use crate::saved_searches::MAX_LIMIT;
+use crate::theme::paint_stderr;
+use crate::{call_api_all, error, geo, network_session, query, AppConfig, Params, Session};
+
+/// Sort of harvests if none is given: the default sort of the site, with the
+/// record id breaking ties so that the order of the pages stays the same.
+const DEFAULT_SORT: &str = "relevance,id asc";
+
+/// Fetch every record of a search to a JSON Lines file
+#[derive(StructOpt, Debug)]
+pub struct HarvestOpts {
+    #[structopt(flatten)]
+    params: Params,
+    /// JSON Lines file to write the records to
+    #[structopt(long, short, parse(from_os_str))]
+    output: PathBuf,
+    /// Include the raw data (the original index record) of each record
+    #[structopt(long)]
+    raw: bool,
+    /// Start over instead of resuming an interrupted harvest
+    #[structopt(long)]
+    restart: bool,
+}
+
+/// Progress of a harvest, saved after each group of pages.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Checkpoint {
+    /// Query of the first page, to recognise the search
+    query: String,
+    next_page: i32,
+    written: usize,
+    /// Length of the output file up to the last complete page
+    output_len: u64,
+    result_count: i32,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Page {
+    #[serde(default)]
+    result_count: i32,
+    #[serde(default)]
+    records: Vec<Value>,
+}
+
+#[derive(Debug, PartialEq)]
+struct Harvested {
+    written: usize,
+    result_count: i32,
+}
+
+pub fn harvest(opts: &HarvestOpts, app_config: &AppConfig) {
+    // All pages are harvested, as many records at a time as the API allows
+    let defaults = Params::from_iter(&["finna"]);
+    if opts.params.limit != defaults.limit || opts.params.page != defaults.page {
+        error(t("--limit and --page can't be used with harvest"));
+        return;
+    }
+    let mut session = match network_session(app_config) {
+        Ok(session) => session,
+        Err(e) => {
+            error(&e);
+            return;
+        }
+    };
+    let mut params = opts.params.clone();
+    geo::add_location_filters(&mut params);
+    params.limit = MAX_LIMIT;
+    params.page = 1;
+    if params.sort.is_none() {
+        params.sort = Some(DEFAULT_SORT.to_string());
+    }
+    // Fields of the result view unless given with --field
+    if params.field.iter().all(|field| field == "[]") {
+        params.field = session.view().fields;
+    }
+    if opts.raw {
+        params.field.push("rawData".to_string());
+    }
+
+    match run(&params, &opts.output, opts.restart, &mut session) {
+        Ok(harvested) => {
+            println!(
+                "{}",
+                tr!("Harvested {}/{} records to {}", harvested.written, harvested.result_count, opts.output.display())
+            );
+            if (harvested.written as i64) < harvested.result_count as i64 {
+                error(&tr!(
+                    "{} records fewer than the result count, which may have changed during the harvest",
+                    harvested.result_count as i64 - harvested.written as i64
+                ));
+            }
+        }
+        Err(e) => error(&e),
+    }
+}
+
+fn checkpoint_path(output: &Path) -> PathBuf {
+    let mut name = output.as_os_str().to_os_string();
+    name.push(".checkpoint");
+    PathBuf::from(name)
+}
+
+fn load_checkpoint(path: &Path) -> io::Result<Option<Checkpoint>> {
+    match fs::read_to_string(path) {
+        Ok(data) => Ok(Some(serde_json::from_str(&data)?)),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+fn store_checkpoint(path: &Path, checkpoint: &Checkpoint) -> io::Result<()> {
+    // Written to a temporary file first so that an interrupted write doesn't lose the progress
+    let mut tmp = path.as_os_str().to_os_string();
+    tmp.push(".tmp");
+    fs::write(&tmp, serde_json::to_string_pretty(checkpoint)?)?;
+    fs::rename(tmp, path)
+}
+
+/// Harvest the pages of `params` to `output`, resuming from a checkpoint of
+/// the same search unless `restart` is set.
+fn run(params: &Params, output: &Path, restart: bool, session: &mut Session) -> Result<Harvested, String> {
+    let query = query::search_query(params).to_string();
+    let checkpoint_file = checkpoint_path(output);
+    let saved = if restart {
+        None
+    } else {
+        load_checkpoint(&checkpoint_file).map_err(|e| tr!("Error reading checkpoint: {}", e))?
+    };
+    let mut checkpoint = match saved {
+        Some(checkpoint) if checkpoint.query == query => {
+            eprintln!("{}", paint_stderr("debug", &tr!("Resuming from page {}", checkpoint.next_page)));
+            checkpoint
+        }
+        Some(_) => return Err(tr!("{} is a checkpoint of another search, use --restart to start over", checkpoint_file.display())),
+        None => Checkpoint {
+            query,
+            next_page: 1,
+            written: 0,
+            output_len: 0,
+            result_count: 0,
+        },
+    };
+
+    let write_error = |e: io::Error| tr!("Error writing output: {}", e);
+    let mut file = OpenOptions::new().write(true).create(true).truncate(false).open(output).map_err(write_error)?;
+    // Drop anything written after the last complete page
+    file.set_len(checkpoint.output_len).map_err(write_error)?;
+    file.seek(SeekFrom::End(0)).map_err(write_error)?;
+    let mut out = BufWriter::new(file);
+
+    let concurrency = session.app_config.http.concurrency.max(1) as i32;
+    // Known after the first page
+    let mut last_page: Option<i32> = None;
+    loop {
+        let count = match last_page {
+            Some(last) => (last - checkpoint.next_page + 1).min(concurrency),
+            None => 1,
+        };
+        if count <= 0 {
+            break;
+        }
+        let urls: Vec<String> = (checkpoint.next_page..checkpoint.next_page + count)
+            .map(|page| {
+                let params = Params { page, ..params.clone() };
+                format!("{}/search?{}", session.app_config.api_url, query::search_query(&params))
+            })
+            .collect();
+        let responses = call_api_all(&urls, session).unwrap_or_default();
+
+        let mut done = false;
+        let mut failed = responses.len() < urls.len();
+        for response in responses {
+            let page: Page = match response.map(|response| response.json()) {
+                Some(Ok(page)) => page,
+                Some(Err(e)) => {
+                    error(&tr!("Error parsing results: {}", e));
+                    failed = true;
+                    break;
+                }
+                None => {
+                    failed = true;
+                    break;
+                }
+            };
+            checkpoint.result_count = page.result_count;
+            last_page = Some((page.result_count.max(0) + params.limit - 1) / params.limit);
+            if page.records.is_empty() {
+                done = true;
+                break;
+            }
+            for rec in &page.records {
+                serde_json::to_writer(&mut out, rec).map_err(|e| write_error(e.into()))?;
+                out.write_all(b"\n").map_err(write_error)?;
+            }
+            checkpoint.written += page.records.len();
+            checkpoint.next_page += 1;
+        }
+
+        out.flush().map_err(write_error)?;
+        checkpoint.output_len = out.get_ref().metadata().map_err(write_error)?.len();
+        store_checkpoint(&checkpoint_file, &checkpoint).map_err(|e| tr!("Error saving checkpoint: {}", e))?;
+        if failed {
+            return Err(tr!("Harvest interrupted after {} records, run the same command again to resume", checkpoint.written));
+        }
+        eprintln!(
+            "{}",
+            paint_stderr("debug", &tr!("{}/{} records", checkpoint.written, checkpoint.result_count))
+        );
+        if done {
+            break;
+        }
+    }
+
+    let _ = fs::remove_file(&checkpoint_file);
+    Ok(Harvested {
+        written: checkpoint.written,
+        result_count: checkpoint.result_count,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http;
+    use crate::mock_api::MockApi;
+
+    fn setup(name: &str) -> (MockApi, Session, Params, PathBuf) {
+        let api = MockApi::start();
+        let app_config = AppConfig {
+            api_url: api.url.clone(),
+            ..AppConfig::default()
+        };
+        let session = Session::new(app_config, api.client(&http::HttpConfig::default()));
+        // 45 results, 3 pages of 20
+        let mut params = Params::from_iter(["finna", "kissa", "--sort", "relevance,id asc"].iter().copied());
+        params.field = vec!["id".to_string(), "title".to_string()];
+        let output = std::env::temp_dir().join(format!("finna-harvest-{}-{}.jsonl", name, std::process::id()));
+        let _ = fs::remove_file(&output);
+        let _ = fs::remove_file(checkpoint_path(&output));
+        (api, session, params, output)
+    }
+
+    fn ids(output: &Path) -> Vec<String> {
+        fs::read_to_string(output)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str::<Value>(line).unwrap()["id"].as_str().unwrap().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn all_pages() {
+        let (api, mut session, params, output) = setup("all");
+        let harvested = run(&params, &output, false, &mut session).unwrap();
+        assert_eq!(harvested, Harvested { written: 7, result_count: 45 });
+        assert_eq!(ids(&output)[3], "vaski.5678");
+        assert_eq!(api.requests().len(), 3);
+        assert!(!checkpoint_path(&output).exists());
+        fs::remove_file(&output).unwrap();
+    }
+
+    #[test]
+    fn resume() {
+        let (api, mut session, params, output) = setup("resume");
+        // Two pages done, and part of a third written after the checkpoint
+        fs::write(&output, "{\"id\":\"a\"}\n{\"id\":\"b\"}\n{\"id\":\"c\"}\n{\"id\":\"d\"}\n{\"id\":\"e\"").unwrap();
+        let checkpoint = Checkpoint {
+            query: query::search_query(&params).to_string(),
+            next_page: 3,
+            written: 4,
+            output_len: 44,
+            result_count: 45,
+        };
+        store_checkpoint(&checkpoint_path(&output), &checkpoint).unwrap();
+        let harvested = run(&params, &output, false, &mut session).unwrap();
+        assert_eq!(harvested.written, 7);
+        assert_eq!(ids(&output)[3..], ["d", "helka.9910001234567890", "satakirjastot.1234", "museovirasto.ABC123"]);
+        let requests = api.requests();
+        assert_eq!(requests.len(), 1);
+        assert!(requests[0].contains("page=3"));
+
+        // Not resumed with another search
+        store_checkpoint(&checkpoint_path(&output), &checkpoint).unwrap();
+        let other = Params {
+            lookfor: vec!["koira".to_string()],
+            ..params.clone()
+        };
+        assert!(run(&other, &output, false, &mut session).is_err());
+        assert_eq!(run(&other, &output, true, &mut session).unwrap().written, 7);
+        fs::remove_file(&output).unwrap();
+    }
+}
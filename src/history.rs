@@ -0,0 +1,67 @@
+//! Back/forward navigation between the searches of a session.
+
+use crate::theme::paint;
+use crate::{format_search_summary, Params, SearchResults};
+
+/// Oldest entries are dropped after this.
+const MAX_ENTRIES: usize = 50;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub params: Params,
+    pub results: SearchResults,
+    /// Query string of the search, used by `:finna`
+    pub query: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SearchHistory {
+    entries: Vec<HistoryEntry>,
+    position: usize,
+}
+
+impl SearchHistory {
+    /// Add a search after the current one, discarding any entries that could be
+    /// reached with `forward`. Re-running the current search only updates its results.
+    pub fn push(&mut self, entry: HistoryEntry) {
+        if let Some(current) = self.entries.get_mut(self.position) {
+            if current.params == entry.params {
+                *current = entry;
+                return;
+            }
+            self.entries.truncate(self.position + 1);
+        }
+        self.entries.push(entry);
+        if self.entries.len() > MAX_ENTRIES {
+            self.entries.remove(0);
+        }
+        self.position = self.entries.len() - 1;
+    }
+
+    pub fn back(&mut self) -> Option<&HistoryEntry> {
+        let position = self.position.checked_sub(1)?;
+        self.go(position)
+    }
+
+    pub fn forward(&mut self) -> Option<&HistoryEntry> {
+        self.go(self.position + 1)
+    }
+
+    /// Move to the entry at the given index.
+    pub fn go(&mut self, position: usize) -> Option<&HistoryEntry> {
+        let entry = self.entries.get(position)?;
+        self.position = position;
+        Some(entry)
+    }
+
+    pub fn view(&self) {
+        for (i, entry) in self.entries.iter().enumerate() {
+            println!(
+                "{marker}{num:>3} {summary}",
+                marker = paint("marker", if i == self.position { ">" } else { " " }),
+                num = paint("count", &(i + 1).to_string()),
+                summary = format_search_summary(&entry.params, &entry.results)
+            );
+        }
+    }
+}
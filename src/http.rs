@@ -0,0 +1,592 @@
+//! HTTP client of API calls, running on an async runtime: one persistent
+//! connection pool, timeouts, retries with exponential backoff, a minimum
+//! interval between the starts of requests and a bounded number of concurrent
+//! requests. The transport can be replaced, e.g. to run without network in
+//! tests, or to record and replay the traffic of a session.
+
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use futures::future::{self, Either, Loop};
+use futures::sync::oneshot;
+use futures::{stream, Future, Stream};
+use reqwest::header::{HeaderMap, HeaderValue, USER_AGENT};
+use serde::de::DeserializeOwned;
+use tokio::runtime::Runtime;
+use tokio::timer::Delay;
+
+use crate::i18n::t;
+use crate::{cancel, debug, error};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HttpConfig {
+    /// Seconds to wait for a connection
+    pub connect_timeout: u64,
+    /// Seconds to wait for a response
+    pub timeout: u64,
+    /// Retries of requests that fail with 429 (Too Many Requests), a 5xx status or a network error
+    pub retries: u32,
+    /// Milliseconds before the first retry, doubled for each further retry
+    pub retry_delay: u64,
+    /// Minimum milliseconds between the starts of requests, to stay polite with the public API
+    pub request_interval: u64,
+    /// Requests made at the same time when fetching many pages, records or images
+    pub concurrency: usize,
+    pub user_agent: String,
+    /// Proxy of all requests, e.g. http://proxy:8080. The `http_proxy` and
+    /// `https_proxy` environment variables are used if not set.
+    pub proxy: Option<String>,
+}
+impl Default for HttpConfig {
+    fn default() -> Self {
+        HttpConfig {
+            connect_timeout: 10,
+            timeout: 30,
+            retries: 3,
+            retry_delay: 500,
+            request_interval: 100,
+            concurrency: 4,
+            user_agent: format!("finna-term/{}", env!("CARGO_PKG_VERSION")),
+            proxy: None,
+        }
+    }
+}
+
+/// Status and body of a response.
+#[derive(Debug, Clone)]
+pub struct Response {
+    pub status: u16,
+    pub body: Vec<u8>,
+}
+impl Response {
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+
+    pub fn json<T: DeserializeOwned>(&self) -> serde_json::Result<T> {
+        serde_json::from_slice(&self.body)
+    }
+}
+
+pub type ResponseFuture = Box<dyn Future<Item = Response, Error = String> + Send>;
+
+/// Transports are shared by the requests running on the runtime.
+pub trait Transport: Send + Sync {
+    fn get(&self, url: &str) -> ResponseFuture;
+}
+
+/// Requests over the network, reusing connections.
+pub struct ReqwestTransport {
+    client: reqwest::r#async::Client,
+}
+impl ReqwestTransport {
+    pub fn new(config: &HttpConfig) -> Result<Self, String> {
+        let mut headers = HeaderMap::new();
+        let user_agent = HeaderValue::from_str(&config.user_agent).map_err(|e| tr!("Invalid user agent: {}", e))?;
+        headers.insert(USER_AGENT, user_agent);
+        let builder = reqwest::r#async::Client::builder()
+            .default_headers(headers)
+            .connect_timeout(Duration::from_secs(config.connect_timeout))
+            .timeout(Duration::from_secs(config.timeout));
+        let builder = match &config.proxy {
+            Some(proxy) => builder.proxy(reqwest::Proxy::all(proxy.as_str()).map_err(|e| tr!("Invalid proxy: {}", e))?),
+            None => builder.use_sys_proxy(),
+        };
+        let client = builder.build().map_err(|e| e.to_string())?;
+        Ok(ReqwestTransport { client })
+    }
+}
+impl Transport for ReqwestTransport {
+    fn get(&self, url: &str) -> ResponseFuture {
+        let response = self.client.get(url).send().and_then(|response| {
+            let status = response.status().as_u16();
+            response.into_body().concat2().map(move |body| Response {
+                status,
+                body: body.to_vec(),
+            })
+        });
+        Box::new(response.map_err(|e| e.to_string()))
+    }
+}
+
+/// A request and its response, as saved by `RecordingTransport`. Bodies that
+/// aren't UTF-8, such as images, are saved in base64.
+#[derive(Debug, Serialize, Deserialize)]
+struct Exchange {
+    url: String,
+    status: u16,
+    #[serde(default)]
+    body: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    body_base64: Option<String>,
+}
+
+/// Saves every request made through another transport to a directory, one
+/// numbered JSON file per request, in the order the responses arrive.
+pub struct RecordingTransport {
+    inner: Box<dyn Transport>,
+    dir: PathBuf,
+    count: Arc<AtomicUsize>,
+}
+impl RecordingTransport {
+    /// Numbering continues after any files already in `dir`.
+    pub fn new(inner: Box<dyn Transport>, dir: &Path) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        let count = exchange_files(dir)?.len();
+        Ok(RecordingTransport {
+            inner,
+            dir: dir.to_path_buf(),
+            count: Arc::new(AtomicUsize::new(count)),
+        })
+    }
+}
+impl Transport for RecordingTransport {
+    fn get(&self, url: &str) -> ResponseFuture {
+        let (url, dir, count) = (url.to_string(), self.dir.clone(), self.count.clone());
+        Box::new(self.inner.get(&url).map(move |response| {
+            let (body, body_base64) = match String::from_utf8(response.body.clone()) {
+                Ok(body) => (body, None),
+                Err(_) => (String::new(), Some(base64::encode(&response.body))),
+            };
+            let exchange = Exchange {
+                url,
+                status: response.status,
+                body,
+                body_base64,
+            };
+            let num = count.fetch_add(1, Ordering::SeqCst) + 1;
+            let path = dir.join(format!("{:04}.json", num));
+            if let Err(e) = serde_json::to_string_pretty(&exchange).map_err(io::Error::from).and_then(|data| fs::write(&path, data)) {
+                error(&tr!("Error saving traffic: {}", e));
+            }
+            response
+        }))
+    }
+}
+
+/// Answers requests with the responses saved by `RecordingTransport`. A url
+/// requested several times gets its responses in the recorded order, and the
+/// last one after that.
+pub struct ReplayTransport {
+    responses: Mutex<HashMap<String, VecDeque<Response>>>,
+}
+impl ReplayTransport {
+    pub fn new(dir: &Path) -> io::Result<Self> {
+        let mut responses: HashMap<String, VecDeque<Response>> = HashMap::new();
+        for (url, response) in recorded(dir)? {
+            responses.entry(url).or_default().push_back(response);
+        }
+        Ok(ReplayTransport {
+            responses: Mutex::new(responses),
+        })
+    }
+}
+impl Transport for ReplayTransport {
+    fn get(&self, url: &str) -> ResponseFuture {
+        let mut responses = self.responses.lock().unwrap();
+        let response = match responses.get_mut(url) {
+            Some(queue) if queue.len() > 1 => Ok(queue.pop_front().unwrap()),
+            Some(queue) if !queue.is_empty() => Ok(queue[0].clone()),
+            _ => Err(tr!("No recorded response for {}", url)),
+        };
+        Box::new(future::result(response))
+    }
+}
+
+/// Urls and responses saved by `RecordingTransport`, in the order they were made.
+pub fn recorded(dir: &Path) -> io::Result<Vec<(String, Response)>> {
+    let mut exchanges = vec![];
+    for path in exchange_files(dir)? {
+        let exchange: Exchange = serde_json::from_str(&fs::read_to_string(&path)?)?;
+        let body = match exchange.body_base64 {
+            Some(data) => base64::decode(&data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+            None => exchange.body.into_bytes(),
+        };
+        let response = Response {
+            status: exchange.status,
+            body,
+        };
+        exchanges.push((exchange.url, response));
+    }
+    Ok(exchanges)
+}
+
+/// Recorded exchanges in a directory, in the order they were made.
+fn exchange_files(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut files: Vec<PathBuf> = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+        .collect();
+    files.sort();
+    Ok(files)
+}
+
+/// Start times of requests, at least `interval` apart.
+struct Limiter {
+    interval: Duration,
+    next: Mutex<Option<Instant>>,
+}
+impl Limiter {
+    fn next_start(&self) -> Instant {
+        let mut next = self.next.lock().unwrap();
+        let now = Instant::now();
+        let start = next.map_or(now, |next| next.max(now));
+        *next = Some(start + self.interval);
+        start
+    }
+}
+
+/// Prefetched results older than this are fetched again, as they may have changed.
+const PREFETCH_TTL: Duration = Duration::from_secs(120);
+
+/// A request started ahead of time.
+struct Prefetch {
+    url: String,
+    started: Instant,
+    result: mpsc::Receiver<Result<Response, String>>,
+    /// Stops the request when the prefetch is dropped
+    _stop: oneshot::Sender<()>,
+}
+
+pub struct Client {
+    transport: Arc<dyn Transport>,
+    retries: u32,
+    retry_delay: Duration,
+    concurrency: usize,
+    limiter: Arc<Limiter>,
+    runtime: Mutex<Runtime>,
+    prefetched: Mutex<Option<Prefetch>>,
+    prefetch_ttl: Duration,
+}
+impl Client {
+    /// Client of a transport, with the retries, request interval and concurrency of `config`.
+    pub fn new(transport: Box<dyn Transport>, config: &HttpConfig) -> Self {
+        Client {
+            transport: Arc::from(transport),
+            retries: config.retries,
+            retry_delay: Duration::from_millis(config.retry_delay),
+            concurrency: config.concurrency.max(1),
+            limiter: Arc::new(Limiter {
+                interval: Duration::from_millis(config.request_interval),
+                next: Mutex::new(None),
+            }),
+            runtime: Mutex::new(Runtime::new().expect("Error starting async runtime")),
+            prefetched: Mutex::new(None),
+            prefetch_ttl: PREFETCH_TTL,
+        }
+    }
+
+    /// Client of the network.
+    pub fn connect(config: &HttpConfig) -> Result<Self, String> {
+        Ok(Client::new(Box::new(ReqwestTransport::new(config)?), config))
+    }
+
+    /// Get a url, retrying failed requests. Ctrl-C cancels the request. A
+    /// recent successful prefetch of the url is used if there is one.
+    pub fn get(&self, url: &str) -> Result<Response, String> {
+        let prefetched = {
+            let mut prefetched = self.prefetched.lock().unwrap();
+            prefetched.take_if(|prefetch| prefetch.started.elapsed() > self.prefetch_ttl);
+            prefetched.take_if(|prefetch| prefetch.url == url)
+        };
+        if let Some(prefetch) = prefetched {
+            match cancel::request(|| cancel::wait(&prefetch.result).ok_or_else(|| t("Request cancelled").to_string()))? {
+                Ok(response) if response.is_success() => return Ok(response),
+                _ => debug(&tr!("Prefetch of {} failed", url)),
+            }
+        }
+        self.run(self.fetch(url).then(Ok))?
+    }
+
+    /// Start getting a url in the background, for a later `get` of the same
+    /// url. Replaces the previous prefetch.
+    pub fn prefetch(&self, url: &str) {
+        let (sender, result) = mpsc::channel();
+        let stop = self.spawn(self.fetch(url).then(move |response| {
+            let _ = sender.send(response);
+            Ok(())
+        }));
+        *self.prefetched.lock().unwrap() = Some(Prefetch {
+            url: url.to_string(),
+            started: Instant::now(),
+            result,
+            _stop: stop,
+        });
+    }
+
+    /// Get urls, `concurrency` at a time. The results are in the order of the
+    /// urls. Ctrl-C cancels the requests.
+    pub fn get_all(&self, urls: &[String]) -> Result<Vec<Result<Response, String>>, String> {
+        let requests: Vec<_> = urls.iter().map(|url| self.fetch(url).then(Ok)).collect();
+        self.run(stream::iter_ok(requests).buffered(self.concurrency).collect())
+    }
+
+    /// Run requests on the runtime and wait for them. Cancelled requests are
+    /// dropped, so that they aren't retried.
+    fn run<T: Send + 'static>(&self, requests: impl Future<Item = T, Error = ()> + Send + 'static) -> Result<T, String> {
+        let (sender, receiver) = mpsc::channel();
+        let _stop = self.spawn(requests.map(move |result| {
+            let _ = sender.send(result);
+        }));
+        cancel::request(|| cancel::wait(&receiver).ok_or_else(|| t("Request cancelled").to_string()))
+    }
+
+    /// Spawn a future on the runtime until it's done or the returned sender is dropped.
+    fn spawn(&self, future: impl Future<Item = (), Error = ()> + Send + 'static) -> oneshot::Sender<()> {
+        let (stop, stopped) = oneshot::channel();
+        self.runtime.lock().unwrap().spawn(future.select2(stopped).then(|_| Ok(())));
+        stop
+    }
+
+    /// Request with retries, started when the limiter allows.
+    fn fetch(&self, url: &str) -> ResponseFuture {
+        let (transport, limiter, retries, url) = (self.transport.clone(), self.limiter.clone(), self.retries, url.to_string());
+        let attempts = future::loop_fn((0, self.retry_delay), move |(attempt, delay)| {
+            let (transport, url) = (transport.clone(), url.clone());
+            Delay::new(limiter.next_start())
+                .map_err(|e| e.to_string())
+                .and_then(move |_| transport.get(&url).then(Ok))
+                .and_then(move |result: Result<Response, String>| {
+                    let retry = match &result {
+                        Ok(response) => response.status == 429 || response.status >= 500,
+                        Err(_) => true,
+                    };
+                    if !retry || attempt >= retries || cancel::cancelled() {
+                        return Either::A(future::ok(Loop::Break(result)));
+                    }
+                    let reason = match &result {
+                        Ok(response) => format!("HTTP {}", response.status),
+                        Err(e) => e.clone(),
+                    };
+                    debug(&tr!("{}, retry {}/{} in {} ms", reason, attempt + 1, retries, delay.as_millis()));
+                    let retry = Delay::new(Instant::now() + delay)
+                        .map_err(|e| e.to_string())
+                        .map(move |_| Loop::Continue((attempt + 1, delay * 2)));
+                    Either::B(retry)
+                })
+        });
+        Box::new(attempts.and_then(future::result))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Answers with the url and the number of the request.
+    struct Counter(AtomicUsize);
+    impl Transport for Counter {
+        fn get(&self, url: &str) -> ResponseFuture {
+            let num = self.0.fetch_add(1, Ordering::SeqCst) + 1;
+            Box::new(future::ok(Response {
+                status: 200,
+                body: format!("{} {}", url, num).into_bytes(),
+            }))
+        }
+    }
+
+    /// Answers with the statuses in turn, the last one after that.
+    struct Statuses(Mutex<Vec<u16>>);
+    impl Transport for Statuses {
+        fn get(&self, _url: &str) -> ResponseFuture {
+            let mut statuses = self.0.lock().unwrap();
+            let status = if statuses.len() > 1 { statuses.remove(0) } else { statuses[0] };
+            Box::new(future::ok(Response { status, body: vec![] }))
+        }
+    }
+
+    /// Answers with the url after a delay, counting the requests in progress.
+    struct Slow {
+        delay: Duration,
+        active: Arc<AtomicUsize>,
+        max_active: Arc<AtomicUsize>,
+    }
+    impl Transport for Slow {
+        fn get(&self, url: &str) -> ResponseFuture {
+            let (active, max_active, body) = (self.active.clone(), self.max_active.clone(), url.as_bytes().to_vec());
+            let now = active.fetch_add(1, Ordering::SeqCst) + 1;
+            max_active.fetch_max(now, Ordering::SeqCst);
+            Box::new(Delay::new(Instant::now() + self.delay).map_err(|e| e.to_string()).map(move |_| {
+                active.fetch_sub(1, Ordering::SeqCst);
+                Response { status: 200, body }
+            }))
+        }
+    }
+
+    /// Answers 503 to every request, counting them.
+    struct Unavailable(Arc<AtomicUsize>);
+    impl Transport for Unavailable {
+        fn get(&self, _url: &str) -> ResponseFuture {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            Box::new(future::ok(Response { status: 503, body: vec![] }))
+        }
+    }
+
+    /// Answers with bytes that aren't UTF-8, like an image.
+    struct Binary;
+    impl Transport for Binary {
+        fn get(&self, _url: &str) -> ResponseFuture {
+            Box::new(future::ok(Response {
+                status: 200,
+                body: vec![0xff, 0xd8, 0xff, 0x00],
+            }))
+        }
+    }
+
+    fn client(statuses: &[u16], retries: u32, request_interval: u64) -> Client {
+        let config = HttpConfig {
+            retries,
+            retry_delay: 1,
+            request_interval,
+            ..HttpConfig::default()
+        };
+        Client::new(Box::new(Statuses(Mutex::new(statuses.to_vec()))), &config)
+    }
+
+    #[test]
+    fn retry() {
+        assert_eq!(client(&[503, 429, 200], 3, 0).get("x").unwrap().status, 200);
+        assert_eq!(client(&[503, 502, 500, 200], 2, 0).get("x").unwrap().status, 500);
+        // Not retried
+        assert_eq!(client(&[404, 200], 3, 0).get("x").unwrap().status, 404);
+    }
+
+    #[test]
+    fn request_interval() {
+        let client = client(&[200], 0, 30);
+        let start = Instant::now();
+        for _ in 0..3 {
+            client.get("x").unwrap();
+        }
+        assert!(start.elapsed() >= Duration::from_millis(60));
+        // Also between concurrent requests
+        let start = Instant::now();
+        client.get_all(&["a".to_string(), "b".to_string(), "c".to_string()]).unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(60));
+    }
+
+    #[test]
+    fn concurrency() {
+        let max_active = Arc::new(AtomicUsize::new(0));
+        let transport = Slow {
+            delay: Duration::from_millis(50),
+            active: Arc::new(AtomicUsize::new(0)),
+            max_active: max_active.clone(),
+        };
+        let config = HttpConfig {
+            request_interval: 0,
+            concurrency: 3,
+            ..HttpConfig::default()
+        };
+        let client = Client::new(Box::new(transport), &config);
+        let urls: Vec<String> = (1..=9).map(|i| i.to_string()).collect();
+        let start = Instant::now();
+        let responses = client.get_all(&urls).unwrap();
+        let elapsed = start.elapsed();
+        assert_eq!(max_active.load(Ordering::SeqCst), 3);
+        // Three rounds instead of nine requests in turn
+        assert!(elapsed >= Duration::from_millis(150) && elapsed < Duration::from_millis(400), "{:?}", elapsed);
+        // In the order of the urls
+        let bodies: Vec<Vec<u8>> = responses.into_iter().map(|response| response.unwrap().body).collect();
+        assert_eq!(bodies, urls.iter().map(|url| url.clone().into_bytes()).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn prefetch() {
+        let client = Client::new(Box::new(Counter(AtomicUsize::new(0))), &HttpConfig::default());
+        let body = |url| String::from_utf8(client.get(url).unwrap().body).unwrap();
+        client.prefetch("a");
+        assert_eq!(body("a"), "a 1");
+        // Used only once
+        assert_eq!(body("a"), "a 2");
+        // Kept over other requests, replaced by the next prefetch
+        let num = |body: String| body.split(' ').nth(1).unwrap().parse::<usize>().unwrap();
+        client.prefetch("b");
+        client.prefetch("c");
+        let d = num(body("d"));
+        assert!(num(body("c")) < d);
+        assert!(num(body("b")) > d);
+    }
+
+    #[test]
+    fn stale_prefetch() {
+        let mut client = Client::new(Box::new(Counter(AtomicUsize::new(0))), &HttpConfig::default());
+        client.prefetch_ttl = Duration::from_millis(0);
+        client.prefetch("a");
+        std::thread::sleep(Duration::from_millis(20));
+        // Fetched again
+        assert_eq!(client.get("a").unwrap().body, b"a 2");
+    }
+
+    #[test]
+    fn stop() {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let config = HttpConfig {
+            retries: 10,
+            retry_delay: 20,
+            ..HttpConfig::default()
+        };
+        let client = Client::new(Box::new(Unavailable(requests.clone())), &config);
+        let stop = client.spawn(client.fetch("x").then(|_| Ok(())));
+        std::thread::sleep(Duration::from_millis(30));
+        drop(stop);
+        let count = requests.load(Ordering::SeqCst);
+        // No more retries after the first ones at 0 and 20 ms
+        std::thread::sleep(Duration::from_millis(100));
+        assert_eq!(requests.load(Ordering::SeqCst), count);
+        assert!(count <= 2);
+    }
+
+    #[test]
+    fn invalid_config() {
+        let proxy = HttpConfig {
+            proxy: Some("not a url".to_string()),
+            ..HttpConfig::default()
+        };
+        assert!(Client::connect(&proxy).is_err());
+        let user_agent = HttpConfig {
+            user_agent: "finna\n".to_string(),
+            ..HttpConfig::default()
+        };
+        assert!(Client::connect(&user_agent).is_err());
+    }
+
+    #[test]
+    fn record_and_replay() {
+        let dir = std::env::temp_dir().join(format!("finna-traffic-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let recording = RecordingTransport::new(Box::new(Counter(AtomicUsize::new(0))), &dir).unwrap();
+        for url in ["http://a/search?x", "http://a/record?y", "http://a/search?x"] {
+            recording.get(url).wait().unwrap();
+        }
+        assert_eq!(exchange_files(&dir).unwrap().len(), 3);
+        assert!(dir.join("0001.json").exists());
+
+        let replay = ReplayTransport::new(&dir).unwrap();
+        let body = |url| replay.get(url).wait().map(|response| String::from_utf8(response.body).unwrap());
+        assert_eq!(body("http://a/search?x").unwrap(), "http://a/search?x 1");
+        assert_eq!(body("http://a/search?x").unwrap(), "http://a/search?x 3");
+        assert_eq!(body("http://a/search?x").unwrap(), "http://a/search?x 3");
+        assert_eq!(body("http://a/record?y").unwrap(), "http://a/record?y 2");
+        assert!(body("http://a/record?z").is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn record_binary() {
+        let dir = std::env::temp_dir().join(format!("finna-traffic-binary-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let recording = RecordingTransport::new(Box::new(Binary), &dir).unwrap();
+        recording.get("http://a/image").wait().unwrap();
+        let replay = ReplayTransport::new(&dir).unwrap();
+        assert_eq!(replay.get("http://a/image").wait().unwrap().body, [0xff, 0xd8, 0xff, 0x00]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,211 @@
+//! Translations of user interface strings.
+//!
+//! Strings are looked up by their English text, which is also used when a
+//! string is missing from the catalogue. `tr!` translates a format string and
+//! fills in its `{}` placeholders in order.
+
+use std::fmt::Display;
+use std::str::FromStr;
+use std::sync::RwLock;
+
+/// Translate a format string and fill in its placeholders, e.g.
+/// `tr!("Unknown view: {}", name)`.
+macro_rules! tr {
+    ($text:expr, $($arg:expr),+ $(,)?) => {
+        $crate::i18n::format($crate::i18n::t($text), &[$(&$arg as &dyn std::fmt::Display),+])
+    };
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Lang {
+    Fi,
+    Sv,
+    En,
+}
+impl FromStr for Lang {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fi" => Ok(Lang::Fi),
+            "sv" => Ok(Lang::Sv),
+            "en" | "en-gb" => Ok(Lang::En),
+            _ => Err(tr!("Unsupported language: {} (expected fi, sv or en)", s)),
+        }
+    }
+}
+impl Lang {
+    /// Value of the `lng` search parameter for the language.
+    pub fn api_code(self) -> &'static str {
+        match self {
+            Lang::Fi => "fi",
+            Lang::Sv => "sv",
+            Lang::En => "en-gb",
+        }
+    }
+}
+
+static LANG: RwLock<Lang> = RwLock::new(Lang::Fi);
+
+pub fn set(lang: Lang) {
+    *LANG.write().unwrap() = lang;
+}
+
+/// Follow the `lng` search parameter, keeping the current language if it is not a UI language.
+pub fn follow(lng: &str) {
+    if let Ok(lang) = lng.parse() {
+        set(lang);
+    }
+}
+
+/// English, Finnish and Swedish.
+const MESSAGES: &[(&str, &str, &str)] = &[
+    // Search results
+    ("{} results, page {}", "{} tulosta, sivu {}", "{} träffar, sida {}"),
+    ("{} results", "{} tulosta", "{} träffar"),
+    ("or", "tai", "eller"),
+    ("not", "ei", "inte"),
+    ("notes", "muistiinpanot", "anteckningar"),
+    ("pinned", "kiinnitetyt", "fästa"),
+    ("{} (lines, compact, table or labelled)", "{} (lines, compact, table tai labelled)", "{} (lines, compact, table eller labelled)"),
+    // Record fields
+    ("Title", "Nimeke", "Titel"),
+    ("Authors", "Tekijät", "Upphovspersoner"),
+    ("Year", "Vuosi", "År"),
+    ("Format", "Aineistotyyppi", "Materialtyp"),
+    ("Formats", "Aineistotyypit", "Materialtyper"),
+    ("Building", "Organisaatio", "Organisation"),
+    ("Buildings", "Organisaatiot", "Organisationer"),
+    ("Languages", "Kielet", "Språk"),
+    ("Series", "Sarjat", "Serier"),
+    ("Physical", "Ulkoasu", "Omfång"),
+    ("Images", "Kuvat", "Bilder"),
+    ("Id", "Tunniste", "Id"),
+    ("Summary", "Tiivistelmä", "Sammanfattning"),
+    ("Subjects", "Aiheet", "Ämnen"),
+    ("Online", "Verkossa", "Online"),
+    ("Tags", "Tagit", "Taggar"),
+    // Filter fields
+    ("Language", "Kieli", "Språk"),
+    ("Author", "Tekijä", "Upphovsperson"),
+    ("Subject", "Aihe", "Ämne"),
+    ("Genre", "Genre", "Genre"),
+    ("Region", "Alue", "Område"),
+    ("Era", "Aika", "Tid"),
+    ("Usage rights", "Käyttöoikeudet", "Användningsrättigheter"),
+    ("Sector", "Sektori", "Sektor"),
+    ("Collection", "Kokoelma", "Samling"),
+    ("Location", "Sijainti", "Plats"),
+    ("Notes", "Muistiinpanot", "Anteckningar"),
+    // Messages
+    ("Network error", "Verkkovirhe", "Nätverksfel"),
+    ("Record not found", "Tietuetta ei löytynyt", "Posten hittades inte"),
+    ("Invalid record number", "Virheellinen tietueen numero", "Ogiltigt postnummer"),
+    ("No such search in history", "Hakua ei ole historiassa", "Sökningen finns inte i historiken"),
+    ("Error opening external program", "Ulkoisen ohjelman avaaminen epäonnistui", "Det gick inte att öppna det externa programmet"),
+    ("No images", "Ei kuvia", "Inga bilder"),
+    ("Error saving images: {}", "Virhe kuvien tallennuksessa: {}", "Fel vid sparande av bilder: {}"),
+    ("Saved {}/{} images to {}", "Tallennettu {}/{} kuvaa hakemistoon {}", "Sparade {}/{} bilder i {}"),
+    ("Unknown command", "Tuntematon komento", "Okänt kommando"),
+    ("Unsupported URL", "Osoitetta ei tueta", "Adressen stöds inte"),
+    ("Usage: :session save|load <name>", "Käyttö: :session save|load <nimi>", "Användning: :session save|load <namn>"),
+    ("Error: {}", "Virhe: {}", "Fel: {}"),
+    ("Error reading ids: {}", "Tunnisteiden lukeminen epäonnistui: {}", "Det gick inte att läsa id:n: {}"),
+    ("Fetched {}/{} records", "Haettiin {}/{} tietuetta", "Hämtade {}/{} poster"),
+    ("Harvested {}/{} records to {}", "Haettiin {}/{} tietuetta tiedostoon {}", "Hämtade {}/{} poster till {}"),
+    ("{} records fewer than the result count, which may have changed during the harvest", "{} tietuetta vähemmän kuin tulosmäärä, joka on voinut muuttua haun aikana", "{} poster färre än antalet träffar, som kan ha ändrats under hämtningen"),
+    ("{}/{} records", "{}/{} tietuetta", "{}/{} poster"),
+    ("--limit and --page can't be used with harvest", "--limit ja --page eivät ole käytössä harvest-komennossa", "--limit och --page kan inte användas med harvest"),
+    ("Resuming from page {}", "Jatketaan sivulta {}", "Fortsätter från sida {}"),
+    ("{} is a checkpoint of another search, use --restart to start over", "{} on toisen haun tarkistuspiste, aloita alusta valitsimella --restart", "{} är en kontrollpunkt för en annan sökning, börja om med --restart"),
+    ("Harvest interrupted after {} records, run the same command again to resume", "Haku keskeytyi {} tietueen jälkeen, jatka ajamalla sama komento uudelleen", "Hämtningen avbröts efter {} poster, fortsätt genom att köra samma kommando igen"),
+    ("Error reading checkpoint: {}", "Virhe tarkistuspisteen lukemisessa: {}", "Fel vid läsning av kontrollpunkt: {}"),
+    ("Error saving checkpoint: {}", "Virhe tarkistuspisteen tallennuksessa: {}", "Fel vid sparande av kontrollpunkt: {}"),
+    ("Error parsing results: {}", "Virhe tulosten käsittelyssä: {}", "Fel vid tolkning av resultat: {}"),
+    ("Invalid date: {} (expected YYYY-MM-DD or YYYY-MM-DDThh:mm:ssZ)", "Virheellinen päivämäärä: {} (muoto VVVV-KK-PP tai VVVV-KK-PPThh:mm:ssZ)", "Ogiltigt datum: {} (formatet ÅÅÅÅ-MM-DD eller ÅÅÅÅ-MM-DDThh:mm:ssZ)"),
+    ("Error parsing OAI-PMH response: {}", "Virhe OAI-PMH-vastauksen käsittelyssä: {}", "Fel vid tolkning av OAI-PMH-svar: {}"),
+    ("OAI-PMH error {}: {}", "OAI-PMH-virhe {}: {}", "OAI-PMH-fel {}: {}"),
+    ("Saved {} records to {}, {} deleted", "Tallennettu {} tietuetta hakemistoon {}, {} poistettu", "Sparade {} poster i {}, {} raderade"),
+    ("Harvest interrupted, continue with --resumption-token {}", "Haku keskeytyi, jatka valitsimella --resumption-token {}", "Hämtningen avbröts, fortsätt med --resumption-token {}"),
+    ("{} records", "{} tietuetta", "{} poster"),
+    ("Error reading local records: {}", "Virhe paikallisten tietueiden lukemisessa: {}", "Fel vid läsning av lokala poster: {}"),
+    ("{} records in the local index", "Paikallisessa hakemistossa {} tietuetta", "{} poster i det lokala indexet"),
+    ("Usage: :set limit|page|type|sort|lng <value>", "Käyttö: :set limit|page|type|sort|lng <arvo>", "Användning: :set limit|page|type|sort|lng <värde>"),
+    ("Usage: :filter add|not|or <filter>, :filter rm <num>, :filter clear", "Käyttö: :filter add|not|or <rajaus>, :filter rm <numero>, :filter clear", "Användning: :filter add|not|or <filter>, :filter rm <nummer>, :filter clear"),
+    ("Invalid filter: {} (expected field:value)", "Virheellinen rajaus: {} (kenttä:arvo)", "Ogiltigt filter: {} (fält:värde)"),
+    ("Unknown parameter: {}", "Tuntematon parametri: {}", "Okänd parameter: {}"),
+    ("Invalid limit: {} (expected 1-{})", "Virheellinen tulosmäärä: {} (1-{})", "Ogiltigt antal träffar: {} (1-{})"),
+    ("Invalid page: {}", "Virheellinen sivu: {}", "Ogiltig sida: {}"),
+    ("No such filter: {}", "Rajausta ei ole: {}", "Filtret finns inte: {}"),
+    ("Invalid coordinates: {}", "Virheelliset koordinaatit: {}", "Ogiltiga koordinater: {}"),
+    ("Coordinates out of range: {}, {}", "Koordinaatit eivät ole sallitulla alueella: {}, {}", "Koordinaterna är utanför tillåtet område: {}, {}"),
+    ("within {} km of {}", "{} km säteellä kohteesta {}", "inom {} km från {}"),
+    ("Error saving traffic: {}", "Virhe liikenteen tallennuksessa: {}", "Fel vid sparande av trafik: {}"),
+    ("No recorded response for {}", "Ei tallennettua vastausta: {}", "Inget sparat svar: {}"),
+    ("Error opening traffic directory: {}", "Virhe liikennehakemiston avauksessa: {}", "Fel vid öppning av trafikkatalog: {}"),
+    ("Invalid user agent: {}", "Virheellinen User-Agent: {}", "Ogiltig User-Agent: {}"),
+    ("Invalid proxy: {}", "Virheellinen välityspalvelin: {}", "Ogiltig proxyserver: {}"),
+    ("{}, retry {}/{} in {} ms", "{}, uusi yritys {}/{} {} ms kuluttua", "{}, nytt försök {}/{} om {} ms"),
+    ("Searching… (Ctrl-C cancels)", "Haetaan… (Ctrl-C peruuttaa)", "Söker… (Ctrl-C avbryter)"),
+    ("Request cancelled", "Haku peruutettu", "Sökningen avbröts"),
+    ("Prefetch of {} failed", "Esihaku {} epäonnistui", "Förhämtning av {} misslyckades"),
+    ("No years in the results", "Tuloksissa ei ole vuosia", "Träffarna har inga årtal"),
+    ("No results in {}", "Ei tuloksia: {}", "Inga träffar: {}"),
+    ("Invalid bar number", "Virheellinen pylvään numero", "Ogiltigt stapelnummer"),
+    ("No locations on this page", "Tämän sivun tietueilla ei ole sijaintia", "Posterna på den här sidan har ingen plats"),
+    ("{} of {} records have a location", "{}/{} tietueella on sijainti", "{} av {} poster har en plats"),
+    ("Unsupported language: {} (expected fi, sv or en)", "Kieltä ei tueta: {} (fi, sv tai en)", "Språket stöds inte: {} (fi, sv eller en)"),
+    ("Unknown layout: {} (expected lines, compact, table or labelled)", "Tuntematon asettelu: {} (lines, compact, table tai labelled)", "Okänd layout: {} (lines, compact, table eller labelled)"),
+    ("Unknown view: {}", "Tuntematon näkymä: {}", "Okänd vy: {}"),
+    ("Unknown field set: {}", "Tuntematon kenttäjoukko: {}", "Okänd fältuppsättning: {}"),
+    ("{} (using the default view)", "{} (käytetään oletusnäkymää)", "{} (standardvyn används)"),
+    ("Missing field name in {}", "Kentän nimi puuttuu: {}", "Fältnamn saknas: {}"),
+    ("Invalid width in {}", "Virheellinen leveys: {}", "Ogiltig bredd: {}"),
+    ("Unclosed {}", "Sulkematon {}", "Oavslutad {}"),
+    ("Unknown theme: {}", "Tuntematon teema: {}", "Okänt tema: {}"),
+    ("Unknown colour mode: {} (expected auto, always or never)", "Tuntematon väritila: {} (auto, always tai never)", "Okänt färgläge: {} (auto, always eller never)"),
+    ("Unknown format: {} (expected text, json, raw or full)", "Tuntematon muoto: {} (text, json, raw tai full)", "Okänt format: {} (text, json, raw eller full)"),
+    ("Unknown format: {} (expected text, mbox or json)", "Tuntematon muoto: {} (text, mbox tai json)", "Okänt format: {} (text, mbox eller json)"),
+    ("{} pinned records", "{} kiinnitettyä tietuetta", "{} fästa poster"),
+    ("{} (using the default theme)", "{} (käytetään oletusteemaa)", "{} (standardtemat används)"),
+    ("Error loading annotations: {}", "Muistiinpanojen lataaminen epäonnistui: {}", "Det gick inte att läsa in anteckningarna: {}"),
+    ("Error saving annotations: {}", "Muistiinpanojen tallentaminen epäonnistui: {}", "Det gick inte att spara anteckningarna: {}"),
+    ("Saved search {} ({} records seen)", "Haku {} tallennettu ({} tietuetta nähty)", "Sökningen {} sparad ({} poster sedda)"),
+    ("{}: {} new records", "{}: {} uutta tietuetta", "{}: {} nya poster"),
+    ("Unknown saved search: {}", "Tuntematon tallennettu haku: {}", "Okänd sparad sökning: {}"),
+    ("Error loading saved searches: {}", "Tallennettujen hakujen lataaminen epäonnistui: {}", "Det gick inte att läsa in sparade sökningar: {}"),
+    ("Error saving search: {}", "Haun tallentaminen epäonnistui: {}", "Det gick inte att spara sökningen: {}"),
+    ("Error saving searches: {}", "Hakujen tallentaminen epäonnistui: {}", "Det gick inte att spara sökningarna: {}"),
+    ("Error writing output: {}", "Tulosteen kirjoittaminen epäonnistui: {}", "Det gick inte att skriva utdata: {}"),
+    ("Saved session {}", "Istunto {} tallennettu", "Sessionen {} sparad"),
+    ("Restored session {} (saved {})", "Istunto {} palautettu (tallennettu {})", "Sessionen {} återställd (sparad {})"),
+    ("Unknown session: {}", "Tuntematon istunto: {}", "Okänd session: {}"),
+    ("New session {}, saved on exit", "Uusi istunto {}, tallennetaan lopetettaessa", "Ny session {}, sparas vid avslut"),
+    ("Error loading sessions: {}", "Istuntojen lataaminen epäonnistui: {}", "Det gick inte att läsa in sessionerna: {}"),
+    ("Error saving session: {}", "Istunnon tallentaminen epäonnistui: {}", "Det gick inte att spara sessionen: {}"),
+];
+
+/// Translate a string to the current language.
+pub fn t(text: &'static str) -> &'static str {
+    let lang = *LANG.read().unwrap();
+    match MESSAGES.iter().find(|message| message.0 == text) {
+        Some((_, fi, _)) if lang == Lang::Fi => fi,
+        Some((_, _, sv)) if lang == Lang::Sv => sv,
+        _ => text,
+    }
+}
+
+/// Replace the `{}` placeholders of a format string with `args` in order.
+pub fn format(text: &str, args: &[&dyn Display]) -> String {
+    let mut args = args.iter();
+    let mut out = String::new();
+    for (i, part) in text.split("{}").enumerate() {
+        if i > 0 {
+            if let Some(arg) = args.next() {
+                out.push_str(&arg.to_string());
+            }
+        }
+        out.push_str(part);
+    }
+    out
+}
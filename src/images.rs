@@ -0,0 +1,79 @@
+//! Downloading the images of a result page.
+
+use std::fs;
+use std::path::Path;
+
+use crate::i18n::t;
+use crate::{call_api_all, error, Record, Session};
+
+/// Directory of `:download` if none is given.
+pub const DEFAULT_DIR: &str = "finna_images";
+
+/// Save the first image of each record to `dir`, downloading them concurrently.
+/// The files are named after the record ids.
+pub fn download(records: &[Record], dir: &Path, session: &mut Session) {
+    let images: Vec<(&str, String)> = records
+        .iter()
+        .filter_map(|rec| Some((rec.id.as_deref()?, rec.images.first()?)))
+        .map(|(id, img)| (id, format!("{}{}", session.app_config.site_url, img)))
+        .collect();
+    if images.is_empty() {
+        println!("{}", t("No images"));
+        return;
+    }
+    if let Err(e) = fs::create_dir_all(dir) {
+        error(&tr!("Error saving images: {}", e));
+        return;
+    }
+    let urls: Vec<String> = images.iter().map(|(_, url)| url.clone()).collect();
+    let responses = match call_api_all(&urls, session) {
+        Some(responses) => responses,
+        None => return,
+    };
+    let mut saved = 0;
+    for ((id, _), response) in images.iter().zip(responses) {
+        if let Some(response) = response {
+            let path = dir.join(format!("{}.{}", file_name(id), extension(&response.body)));
+            match fs::write(&path, &response.body) {
+                Ok(()) => saved += 1,
+                Err(e) => error(&tr!("Error saving images: {}", e)),
+            }
+        }
+    }
+    println!("{}", tr!("Saved {}/{} images to {}", saved, images.len(), dir.display()));
+}
+
+/// Record id as a file name, e.g. `helka.123/4` as `helka.123_4`.
+pub fn file_name(id: &str) -> String {
+    id.chars()
+        .map(|c| if c.is_alphanumeric() || "-_.".contains(c) { c } else { '_' })
+        .collect()
+}
+
+/// File extension of image data by its signature, JPEG if not recognised.
+fn extension(data: &[u8]) -> &'static str {
+    if data.starts_with(b"\x89PNG") {
+        "png"
+    } else if data.starts_with(b"GIF8") {
+        "gif"
+    } else if data.starts_with(b"RIFF") && data.get(8..12) == Some(b"WEBP") {
+        "webp"
+    } else {
+        "jpg"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names() {
+        assert_eq!(file_name("helka.9910001234567890"), "helka.9910001234567890");
+        assert_eq!(file_name("museovirasto.AB/12 3"), "museovirasto.AB_12_3");
+        assert_eq!(extension(b"\x89PNG\r\n"), "png");
+        assert_eq!(extension(b"GIF89a"), "gif");
+        assert_eq!(extension(b"RIFF\0\0\0\0WEBPVP8"), "webp");
+        assert_eq!(extension(b"\xff\xd8\xff"), "jpg");
+    }
+}
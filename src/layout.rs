@@ -0,0 +1,169 @@
+//! Display width aware layout of search results.
+
+use std::str::FromStr;
+
+use regex::Regex;
+use tabular::{Row, Table};
+use terminal_size::{terminal_size, Width};
+use unicode_width::UnicodeWidthChar;
+use unicode_width::UnicodeWidthStr;
+
+use crate::annotations::{Annotation, Annotations};
+use crate::i18n::t;
+use crate::theme::paint;
+use crate::views;
+use crate::Record;
+
+/// Used when the output is not a terminal.
+const DEFAULT_WIDTH: usize = 80;
+const ELLIPSIS: &str = "…";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Layout {
+    /// Both lines of the result view
+    #[default]
+    Lines,
+    /// First line of the result view only
+    Compact,
+    Table,
+    /// One line per record with labelled values, for screen readers
+    Labelled,
+}
+impl FromStr for Layout {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "lines" => Ok(Layout::Lines),
+            "compact" => Ok(Layout::Compact),
+            "table" => Ok(Layout::Table),
+            "labelled" => Ok(Layout::Labelled),
+            _ => Err(tr!("Unknown layout: {} (expected lines, compact, table or labelled)", s)),
+        }
+    }
+}
+
+pub fn terminal_width() -> usize {
+    match terminal_size() {
+        Some((Width(width), _)) => width as usize,
+        None => std::env::var("COLUMNS")
+            .ok()
+            .and_then(|columns| columns.parse().ok())
+            .unwrap_or(DEFAULT_WIDTH),
+    }
+}
+
+lazy_static! {
+    static ref COLOUR_ESCAPES: Regex = Regex::new("\x1b\\[[0-9;]*m").unwrap();
+}
+
+/// Display width of text that may contain colour escape codes.
+pub fn visible_width(text: &str) -> usize {
+    COLOUR_ESCAPES.replace_all(text, "").width()
+}
+
+/// Truncate text to `width` columns, ending it with an ellipsis if it was cut.
+/// Combining marks stay with their base character.
+pub fn truncate(text: &str, width: usize) -> String {
+    if text.width() <= width {
+        return text.to_string();
+    }
+    let limit = width.saturating_sub(ELLIPSIS.width());
+    let mut used = 0;
+    let mut out = String::new();
+    for c in text.chars() {
+        let char_width = c.width().unwrap_or(0);
+        if used + char_width > limit {
+            break;
+        }
+        used += char_width;
+        out.push(c);
+    }
+    if width > 0 {
+        out.push_str(ELLIPSIS);
+    }
+    out
+}
+
+/// Pad text with spaces to `width` columns.
+pub fn pad(text: &str, width: usize, right_align: bool) -> String {
+    let fill = " ".repeat(width.saturating_sub(text.width()));
+    if right_align {
+        fill + text
+    } else {
+        text.to_string() + &fill
+    }
+}
+
+/// Format records as a table fitted to `width` columns.
+pub fn format_table(records: &[Record], annotations: &Annotations, width: usize) -> String {
+    let cells: Vec<[String; 7]> = records
+        .iter()
+        .enumerate()
+        .map(|(i, rec)| {
+            let annotated = rec.id.as_ref().and_then(|id| annotations.get(id)).is_some();
+            [
+                (i + 1).to_string(),
+                if annotated { "✎".to_string() } else { "".to_string() },
+                rec.title.clone().unwrap_or_default(),
+                rec.year.clone().unwrap_or_default(),
+                rec.formats.last().map(|format| format.translated.clone()).unwrap_or_default(),
+                views::authors(rec).join(" | "),
+                rec.buildings.first().map(|building| building.translated.clone()).unwrap_or_default(),
+            ]
+        })
+        .collect();
+    let headings = ["#", "", t("Title"), t("Year"), t("Format"), t("Authors"), t("Building")];
+
+    // Fixed maximum widths for the other columns, the title gets the rest
+    let max_widths = [4, 1, 0, 4, 16, 24, 20];
+    let mut widths = [0; 7];
+    for column in 0..7 {
+        widths[column] = cells
+            .iter()
+            .map(|row| row[column].width())
+            .chain(std::iter::once(headings[column].width()))
+            .max()
+            .unwrap_or(0)
+            .min(max_widths[column]);
+    }
+    let separators = 2 * (widths.len() - 1);
+    let others: usize = widths.iter().sum::<usize>() + separators;
+    widths[2] = width.saturating_sub(others).max(20);
+
+    let mut table = Table::new("{:>}  {:<}  {:<}  {:<}  {:<}  {:<}  {:<}");
+    table.add_row(Row::from_cells(headings.iter().enumerate().map(|(column, heading)| truncate(heading, widths[column]))));
+    for row in &cells {
+        table.add_row(Row::from_cells(row.iter().enumerate().map(|(column, cell)| truncate(cell, widths[column]))));
+    }
+    let table = table.to_string();
+    let mut lines = table.lines();
+    let heading = paint("heading", lines.next().unwrap_or("")).to_string();
+    std::iter::once(heading)
+        .chain(lines.map(|line| line.trim_end().to_string()))
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+/// Format a record as a single line of labelled values, `cnt` being its index
+/// on the page. Nothing is truncated, leaving wrapping to the terminal.
+pub fn format_labelled(rec: &Record, cnt: usize, annotation: Option<&Annotation>) -> String {
+    let mut values = vec![
+        (t("Title"), rec.title.clone().unwrap_or_default()),
+        (t("Year"), rec.year.clone().unwrap_or_default()),
+        (t("Format"), rec.formats.last().map(|format| format.translated.clone()).unwrap_or_default()),
+        (t("Authors"), views::authors(rec).join(", ")),
+        (t("Building"), rec.buildings.first().map(|building| building.translated.clone()).unwrap_or_default()),
+    ];
+    if let Some(annotation) = annotation {
+        values.push((t("Tags"), annotation.tags.iter().cloned().collect::<Vec<String>>().join(", ")));
+        values.push((t("Notes"), annotation.notes.join(" / ")));
+    }
+    let values: Vec<String> = values
+        .into_iter()
+        .filter(|(_, value)| !value.is_empty())
+        .map(|(label, value)| format!("{}: {}", label, value))
+        .collect();
+    format!("{}. {}", cnt + 1, values.join("; "))
+}
@@ -0,0 +1,52 @@
+//! Parsing of search and record URLs of finna.fi and other VuFind sites, the
+//! inverse of the URLs built by `:finna`.
+
+use percent_encoding::percent_decode_str;
+use structopt::StructOpt;
+use url::Url;
+
+use crate::Params;
+
+pub enum SiteLink {
+    Search(Box<Params>),
+    Record(String),
+}
+
+/// Parse a `…/Search/Results?…` or `…/Record/<id>` URL.
+pub fn parse(link: &str) -> Option<SiteLink> {
+    let url = Url::parse(link.trim()).ok()?;
+    let segments: Vec<&str> = url.path_segments()?.collect();
+
+    if let Some(pos) = segments.iter().position(|segment| *segment == "Record") {
+        let id = segments.get(pos + 1).filter(|id| !id.is_empty())?;
+        return Some(SiteLink::Record(percent_decode_str(id).decode_utf8_lossy().into_owned()));
+    }
+
+    if !segments.ends_with(&["Search", "Results"]) {
+        return None;
+    }
+    Some(SiteLink::Search(Box::new(search_params(&url))))
+}
+
+/// Search parameters in the query string of a search URL of the site or the API.
+pub fn search_params(url: &Url) -> Params {
+    let mut params = Params::from_iter(&[""]);
+    for (key, value) in url.query_pairs() {
+        match key.as_ref() {
+            "lookfor" => params.lookfor = vec![value.into_owned()],
+            "type" => params.r#type = value.into_owned(),
+            "sort" => params.sort = Some(value.into_owned()),
+            "lng" => params.lng = Some(value.into_owned()),
+            "page" => params.page = value.parse().unwrap_or(1),
+            "limit" => params.limit = value.parse().unwrap_or(params.limit),
+            // filter[] from the site, filter[0] etc. from our own query strings
+            key if key == "filter" || key.starts_with("filter[") => {
+                if let Ok(filter) = value.parse() {
+                    params.filter.get_or_insert_with(Vec::new).push(filter);
+                }
+            }
+            _ => {}
+        }
+    }
+    params
+}
@@ -0,0 +1,445 @@
+//! Searching harvested records offline. The records of a JSON Lines file
+//! written by `finna harvest`, or of the responses saved with
+//! `--record-traffic`, are indexed by word, and a transport answers the
+//! search and record requests of the API from the index.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use futures::future;
+use serde_json::{json, Value};
+use url::Url;
+
+use crate::filters::{self, Filter, Operator};
+use crate::http::{self, Response, ResponseFuture, Transport};
+use crate::{links, Params, Record, SearchResults};
+
+/// Parts of a record a word was found in, as bits.
+const TITLE: u8 = 1;
+const AUTHOR: u8 = 2;
+const SUBJECT: u8 = 4;
+const OTHER: u8 = 8;
+const ALL: u8 = TITLE | AUTHOR | SUBJECT | OTHER;
+/// Fields not indexed: the id, links and the original record.
+const SKIPPED_FIELDS: [&str; 5] = ["id", "images", "urls", "rawData", "fullRecord"];
+
+/// Records and the record numbers of each word, with the parts of the record
+/// it was found in.
+pub struct LocalIndex {
+    records: Vec<Value>,
+    words: BTreeMap<String, Vec<(usize, u8)>>,
+}
+
+impl LocalIndex {
+    /// Index the records of a JSON Lines file, or of the search and record
+    /// responses saved in a directory. Records are indexed once per id.
+    pub fn load(path: &Path) -> io::Result<LocalIndex> {
+        let records: Vec<Value> = if path.is_dir() {
+            http::recorded(path)?
+                .into_iter()
+                .filter(|(_, response)| response.is_success())
+                .filter_map(|(_, response)| response.json::<Value>().ok())
+                .flat_map(|results| match results.get("records") {
+                    Some(Value::Array(records)) => records.clone(),
+                    _ => vec![],
+                })
+                .collect()
+        } else {
+            let mut records = vec![];
+            for line in fs::read_to_string(path)?.lines().filter(|line| !line.trim().is_empty()) {
+                records.push(serde_json::from_str(line)?);
+            }
+            records
+        };
+        Ok(LocalIndex::new(records))
+    }
+
+    pub fn new(records: Vec<Value>) -> LocalIndex {
+        let mut ids = HashSet::new();
+        let records: Vec<Value> = records
+            .into_iter()
+            .filter(|rec| rec.get("id").and_then(Value::as_str).is_some_and(|id| ids.insert(id.to_string())))
+            .collect();
+        let mut words: BTreeMap<String, Vec<(usize, u8)>> = BTreeMap::new();
+        for (num, rec) in records.iter().enumerate() {
+            let mut found: HashMap<String, u8> = HashMap::new();
+            if let Value::Object(fields) = rec {
+                for (key, value) in fields.iter().filter(|(key, _)| !SKIPPED_FIELDS.contains(&key.as_str())) {
+                    let part = field_part(key);
+                    for word in value_words(value) {
+                        *found.entry(word).or_default() |= part;
+                    }
+                }
+            }
+            for (word, parts) in found {
+                words.entry(word).or_default().push((num, parts));
+            }
+        }
+        LocalIndex { records, words }
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    /// Search the records like the API: `lookfor` words are all required
+    /// unless joined with OR, `-word` or NOT excludes, `word*` matches the
+    /// beginning of words. `type` limits the search to titles, authors or
+    /// subjects, and the filters work on formats, buildings, the year and
+    /// other fields by name.
+    pub fn search(&self, params: &Params) -> SearchResults {
+        let matches = self.matches(params);
+        let records = page(&matches, params)
+            .iter()
+            .filter_map(|&(num, _)| to_record(&self.records[num]))
+            .collect();
+        SearchResults {
+            records,
+            result_count: matches.len() as i32,
+        }
+    }
+
+    /// Records by id, in the order of the ids.
+    pub fn records(&self, ids: &[String]) -> Vec<&Value> {
+        ids.iter()
+            .filter_map(|id| self.records.iter().find(|rec| rec.get("id").and_then(Value::as_str) == Some(id)))
+            .collect()
+    }
+
+    /// Numbers and scores of the matching records, in the order of `params.sort`.
+    fn matches(&self, params: &Params) -> Vec<(usize, usize)> {
+        let parts = match params.r#type.as_str() {
+            "Title" => TITLE,
+            "Author" => AUTHOR,
+            "Subject" => SUBJECT,
+            _ => ALL,
+        };
+        let (groups, excluded) = parse_lookfor(&crate::vec2str(&params.lookfor, " "));
+        let mut scores: Vec<Option<usize>> = vec![Some(0); self.records.len()];
+        for group in &groups {
+            let mut group_scores: Vec<usize> = vec![0; self.records.len()];
+            for word in group {
+                for (num, found) in self.postings(word) {
+                    if found & parts != 0 {
+                        group_scores[num] += if found & TITLE != 0 { 3 } else if found & (AUTHOR | SUBJECT) != 0 { 2 } else { 1 };
+                    }
+                }
+            }
+            for (score, group_score) in scores.iter_mut().zip(group_scores) {
+                *score = score.filter(|_| group_score > 0).map(|score| score + group_score);
+            }
+        }
+        for word in &excluded {
+            for (num, found) in self.postings(word) {
+                if found & parts != 0 {
+                    scores[num] = None;
+                }
+            }
+        }
+
+        let filters = params.filter.as_deref().unwrap_or_default();
+        let mut matches: Vec<(usize, usize)> = scores
+            .into_iter()
+            .enumerate()
+            .filter_map(|(num, score)| Some((num, score?)))
+            .filter(|&(num, _)| filters_match(&self.records[num], filters))
+            .collect();
+        let (field, descending) = match params.sort.as_deref().map(|sort| sort.split_once(' ').unwrap_or((sort, "asc"))) {
+            Some((field, order)) => (field, order == "desc"),
+            None => ("relevance", false),
+        };
+        match field {
+            "main_date_str" | "year" => matches.sort_by_key(|&(num, _)| self.records[num]["year"].as_str().map(str::to_string)),
+            "title" | "id" => matches.sort_by_key(|&(num, _)| self.records[num][field].as_str().map(str::to_lowercase)),
+            // Best first, then in the order of the file
+            _ => matches.sort_by_key(|&(num, score)| (std::cmp::Reverse(score), num)),
+        }
+        if descending {
+            matches.reverse();
+        }
+        matches
+    }
+
+    /// Records with `word`, or with words beginning with it if it ends with `*`.
+    fn postings(&self, word: &str) -> Vec<(usize, u8)> {
+        match word.strip_suffix('*') {
+            Some(prefix) => {
+                let mut found: BTreeMap<usize, u8> = BTreeMap::new();
+                for (_, postings) in self.words.range(prefix.to_string()..).take_while(|(key, _)| key.starts_with(prefix)) {
+                    for &(num, parts) in postings {
+                        *found.entry(num).or_default() |= parts;
+                    }
+                }
+                found.into_iter().collect()
+            }
+            None => self.words.get(word).cloned().unwrap_or_default(),
+        }
+    }
+
+    /// Counts of the values of `field` in the matching records, most common first.
+    fn facet(&self, params: &Params, field: &str) -> Vec<Value> {
+        let mut counts: BTreeMap<String, u64> = BTreeMap::new();
+        for (num, _) in self.matches(params) {
+            for value in field_values(&self.records[num], field) {
+                *counts.entry(value).or_default() += 1;
+            }
+        }
+        let mut counts: Vec<(String, u64)> = counts.into_iter().collect();
+        counts.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+        counts
+            .into_iter()
+            .map(|(value, count)| json!({"value": value, "translated": value, "count": count}))
+            .collect()
+    }
+}
+
+fn field_part(key: &str) -> u8 {
+    let key = key.to_lowercase();
+    if key.contains("title") {
+        TITLE
+    } else if key.contains("author") {
+        AUTHOR
+    } else if key.contains("subject") || key.contains("genre") {
+        SUBJECT
+    } else {
+        OTHER
+    }
+}
+
+/// Lowercase words of text.
+fn words(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric()).filter(|word| !word.is_empty()).map(str::to_lowercase)
+}
+
+/// Words of the strings in a value, at any depth.
+fn value_words(value: &Value) -> Vec<String> {
+    match value {
+        Value::String(text) => words(text).collect(),
+        Value::Array(values) => values.iter().flat_map(value_words).collect(),
+        Value::Object(fields) => fields.values().flat_map(value_words).collect(),
+        _ => vec![],
+    }
+}
+
+/// Groups of words of which one is required, and excluded words.
+fn parse_lookfor(lookfor: &str) -> (Vec<Vec<String>>, Vec<String>) {
+    let mut groups: Vec<Vec<String>> = vec![];
+    let mut excluded = vec![];
+    let mut or = false;
+    let mut not = false;
+    for term in lookfor.split_whitespace() {
+        match term {
+            "AND" | "&&" => continue,
+            "OR" | "||" => {
+                or = true;
+                continue;
+            }
+            "NOT" => {
+                not = true;
+                continue;
+            }
+            _ => {}
+        }
+        let (exclude, term) = match term.strip_prefix('-') {
+            Some(term) => (true, term),
+            None => (not, term.strip_prefix('+').unwrap_or(term)),
+        };
+        let wildcard = term.ends_with('*');
+        let mut term_words: Vec<String> = words(term).collect();
+        if wildcard {
+            if let Some(last) = term_words.last_mut() {
+                last.push('*');
+            }
+        }
+        if exclude {
+            excluded.extend(term_words);
+        } else {
+            // The words of e.g. "e-kirja" are all required
+            for (i, word) in term_words.into_iter().enumerate() {
+                match groups.last_mut() {
+                    Some(group) if or && i == 0 => group.push(word),
+                    _ => groups.push(vec![word]),
+                }
+            }
+        }
+        or = false;
+        not = false;
+    }
+    (groups, excluded)
+}
+
+/// Values of a filter field in a record: the codes of formats and buildings,
+/// the year, or the strings of a field by name.
+fn field_values(rec: &Value, field: &str) -> Vec<String> {
+    let key = match field {
+        "format" => "formats",
+        "building" => "buildings",
+        "main_date_str" | "year" => "year",
+        field => field,
+    };
+    let value = |value: &Value| match value {
+        Value::String(text) => Some(text.clone()),
+        Value::Number(number) => Some(number.to_string()),
+        Value::Object(fields) => fields.get("value").and_then(Value::as_str).map(str::to_string),
+        _ => None,
+    };
+    match rec.get(key) {
+        Some(Value::Array(values)) => values.iter().filter_map(value).collect(),
+        Some(other) => value(other).into_iter().collect(),
+        None => vec![],
+    }
+}
+
+/// Filters of the same field with `~` need one match, the others all.
+fn filters_match(rec: &Value, filters: &[Filter]) -> bool {
+    let has = |filter: &Filter| match filters::year_range(&filter.value) {
+        Some((first, last)) => field_values(rec, &filter.field)
+            .iter()
+            .any(|value| value.trim().parse().is_ok_and(|year: i64| (first..=last).contains(&year))),
+        None => field_values(rec, &filter.field).contains(&filter.value),
+    };
+    let mut or_fields: HashMap<&str, bool> = HashMap::new();
+    for filter in filters {
+        match filter.operator {
+            Operator::And if !has(filter) => return false,
+            Operator::Not if has(filter) => return false,
+            Operator::Or => *or_fields.entry(&filter.field).or_default() |= has(filter),
+            _ => {}
+        }
+    }
+    or_fields.values().all(|&found| found)
+}
+
+fn page<'a>(matches: &'a [(usize, usize)], params: &Params) -> &'a [(usize, usize)] {
+    let limit = params.limit.max(0) as usize;
+    let start = (params.page.max(1) as usize - 1).saturating_mul(limit).min(matches.len());
+    &matches[start..(start + limit).min(matches.len())]
+}
+
+/// Record of the API fields, with the author lists required by `Record`
+/// added if they were not harvested.
+fn to_record(rec: &Value) -> Option<Record> {
+    let mut rec = rec.clone();
+    for key in ["primaryAuthors", "nonPresenterAuthors"] {
+        rec.as_object_mut()?.entry(key).or_insert_with(|| json!([]));
+    }
+    serde_json::from_value(rec).ok()
+}
+
+/// Answers the `/search` and `/record` requests of the API from an index.
+pub struct LocalTransport(pub LocalIndex);
+
+impl LocalTransport {
+    fn answer(&self, url: &str) -> Option<Value> {
+        let url = Url::parse(url).ok()?;
+        match url.path_segments()?.next_back()? {
+            "search" => {
+                let params = links::search_params(&url);
+                let results = self.0.search(&params);
+                let facet_limit = url
+                    .query_pairs()
+                    .find(|(key, _)| key == "facetLimit")
+                    .and_then(|(_, limit)| limit.parse().ok())
+                    .unwrap_or(usize::MAX);
+                let facets: serde_json::Map<String, Value> = url
+                    .query_pairs()
+                    .filter(|(key, _)| key.starts_with("facet["))
+                    .map(|(_, field)| {
+                        let values = self.0.facet(&params, &field).into_iter().take(facet_limit).collect();
+                        (field.to_string(), Value::Array(values))
+                    })
+                    .collect();
+                Some(json!({
+                    "resultCount": results.result_count,
+                    "records": results.records,
+                    "facets": facets,
+                    "status": "OK",
+                }))
+            }
+            "record" => {
+                let ids: Vec<String> = url
+                    .query_pairs()
+                    .filter(|(key, _)| key == "id" || key.starts_with("id["))
+                    .map(|(_, id)| id.into_owned())
+                    .collect();
+                let records = self.0.records(&ids);
+                Some(json!({"resultCount": records.len(), "records": records, "status": "OK"}))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Transport for LocalTransport {
+    fn get(&self, url: &str) -> ResponseFuture {
+        let response = match self.answer(url) {
+            Some(body) => Response {
+                status: 200,
+                body: body.to_string().into_bytes(),
+            },
+            None => Response {
+                status: 404,
+                body: vec![],
+            },
+        };
+        Box::new(future::ok(response))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::Future;
+    use structopt::StructOpt;
+
+    fn index() -> LocalIndex {
+        let fixture = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/search.json");
+        let results: Value = serde_json::from_str(&fs::read_to_string(fixture).unwrap()).unwrap();
+        LocalIndex::new(results["records"].as_array().unwrap().clone())
+    }
+
+    fn ids(args: &[&str]) -> Vec<String> {
+        let params = Params::from_iter(std::iter::once("finna").chain(args.iter().copied()));
+        index().search(&params).records.into_iter().filter_map(|rec| rec.id).collect()
+    }
+
+    #[test]
+    fn lookfor() {
+        assert_eq!(ids(&["kiss*"]).len(), 3);
+        // No stemming
+        assert_eq!(ids(&["kissa"]), ["satakirjastot.1234", "museovirasto.ABC123"]);
+        assert_eq!(ids(&["koira"]), ["satakirjastot.1234"]);
+        assert_eq!(ids(&["--", "kiss*", "-koira"]), ["helka.9910001234567890", "museovirasto.ABC123"]);
+        assert_eq!(ids(&["koira", "OR", "ikkuna*"]), ["satakirjastot.1234", "museovirasto.ABC123"]);
+        assert_eq!(ids(&["--type", "Author", "mäkinen"]), ["satakirjastot.1234"]);
+        assert!(ids(&["--type", "Title", "mäkinen"]).is_empty());
+        assert!(ids(&["hevonen"]).is_empty());
+    }
+
+    #[test]
+    fn filters_and_sort() {
+        assert_eq!(ids(&["--filter", "format:0/Book/", "--sort", "main_date_str desc"]), ["satakirjastot.1234", "helka.9910001234567890"]);
+        assert_eq!(ids(&["--filter=-format:0/Book/"]), ["museovirasto.ABC123"]);
+        assert_eq!(ids(&["--filter", "~main_date_str:2003", "--filter", "~main_date_str:1952"]).len(), 2);
+        assert_eq!(ids(&["--filter", "main_date_str:[1950 TO 1959]"]), ids(&["--filter", "main_date_str:1952"]));
+        assert_eq!(ids(&["--limit", "2", "--page", "2"]), ["museovirasto.ABC123"]);
+    }
+
+    #[test]
+    fn transport() {
+        let transport = LocalTransport(index());
+        let response = transport.get("http://local/api/v1/record?id[0]=satakirjastot.1234").wait().unwrap();
+        let results: SearchResults = response.json().unwrap();
+        assert_eq!(results.records[0].title.as_deref(), Some("Kissa & koira: ystävykset"));
+        let response = transport.get("http://local/api/v1/search?lookfor=kiss%2A&facet[]=main_date_str").wait().unwrap();
+        let results: Value = response.json().unwrap();
+        assert_eq!(results["resultCount"], 3);
+        assert_eq!(results["facets"]["main_date_str"].as_array().unwrap().len(), 3);
+        let response = transport.get("http://local/api/v1/search?lookfor=kiss%2A&facet[]=main_date_str&facetLimit=2").wait().unwrap();
+        let results: Value = response.json().unwrap();
+        assert_eq!(results["facets"]["main_date_str"].as_array().unwrap().len(), 2);
+        assert_eq!(transport.get("http://local/api/v1/nothing").wait().unwrap().status, 404);
+    }
+}
@@ -1,21 +1,34 @@
 use std::collections::HashMap;
 use std::borrow::Cow::{self, Borrowed, Owned};
+use std::fs;
+use std::io::{self, Read};
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::str::FromStr;
 
 extern crate serde;
 extern crate colored;
+#[macro_use]
+extern crate lazy_static;
 extern crate regex;
 extern crate reqwest;
 #[macro_use]
 extern crate serde_derive;
 extern crate serde_json;
-extern crate serde_qs;
 extern crate confy;
+extern crate chrono;
+extern crate directories;
+extern crate percent_encoding;
+extern crate tabular;
+extern crate terminal_size;
+extern crate unicode_width;
+extern crate url;
 
-use colored::*;
 use regex::Regex;
 use serde_json::Value;
 use structopt::StructOpt;
+use i18n::t;
 extern crate open;
 extern crate rustyline;
 use rustyline::error::ReadlineError;
@@ -26,12 +39,39 @@ use rustyline::highlight::{Highlighter, MatchingBracketHighlighter};
 use rustyline::hint::{Hinter, HistoryHinter};
 use rustyline_derive::{Helper};
 
+#[macro_use]
+mod i18n;
+mod annotations;
+mod cancel;
+mod details;
+mod filters;
+mod geo;
+mod harvest;
+mod history;
+mod http;
+mod images;
+mod layout;
+mod links;
+mod local;
+#[cfg(test)]
+mod mock_api;
+mod oai;
+mod query;
+mod saved_searches;
+mod sessions;
+mod settings;
+mod storage;
+mod template;
+mod theme;
+mod timeline;
+mod views;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TranslatedString {
     pub value: String,
     pub translated: String,
 }
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct OnlineUrl {
     #[serde(default)]
     pub url: String,
@@ -68,34 +108,110 @@ pub struct Record {
 
     #[serde(default)]
     pub images: Vec<String>,
+
+    #[serde(default)]
+    pub subjects: Vec<Vec<String>>,
+    #[serde(default)]
+    pub languages: Vec<String>,
+    #[serde(default)]
+    pub series: Vec<Value>,
+    #[serde(default)]
+    pub physical_descriptions: Vec<String>,
+    #[serde(default)]
+    pub online_urls: Vec<OnlineUrl>,
     
     #[serde(flatten)]
     extra: HashMap<String, Value>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct AppConfig {
+    // Plain values come before the tables, which TOML requires
     api_url: String,
     site_url: String,
+    /// Result view used on startup
+    view: String,
+    layout: layout::Layout,
+    /// Colour theme used on startup
+    theme: String,
+    color: theme::ColorMode,
+    /// Labelled lines without colours instead of columns
+    screen_reader: bool,
+    /// Language of the user interface (fi, sv or en), follows the `lng` parameter if not set
+    ui_lng: Option<String>,
+    /// Fetch the next result page in the background, so that `:n` shows it at once
+    prefetch: bool,
+    /// Named lists of API fields to fetch
+    field_sets: HashMap<String, Vec<String>>,
+    views: HashMap<String, views::ViewConfig>,
+    /// Styles of text roles, by theme name
+    themes: HashMap<String, theme::Theme>,
+    http: http::HttpConfig,
 }
 impl ::std::default::Default for AppConfig {
     fn default() -> Self { Self {
         api_url: "https://api.finna.fi/api/v1".into(),
         site_url: "https://finna.fi".into(),
+        view: views::DEFAULT_VIEW.into(),
+        layout: layout::Layout::Lines,
+        theme: theme::DEFAULT_THEME.into(),
+        color: theme::ColorMode::Auto,
+        screen_reader: false,
+        ui_lng: None,
+        prefetch: true,
+        field_sets: views::default_field_sets(),
+        views: views::default_views(),
+        themes: theme::default_themes(),
+        http: http::HttpConfig::default(),
     }}
 }
 
 pub struct Session {
     pub last_search: Option<String>,
-    pub app_config: AppConfig
+    pub app_config: AppConfig,
+    pub annotations: annotations::Annotations,
+    pub history: history::SearchHistory,
+    pub pinned: Vec<Record>,
+    /// Name the session is saved under on exit
+    pub name: Option<String>,
+    /// Result view selected with `:view`, `AppConfig.view` if empty
+    pub view: String,
+    /// Layout selected with `:layout`
+    pub layout: Option<layout::Layout>,
+    pub client: http::Client,
+    /// Print the url of each API request
+    pub verbose: bool,
 }
-impl Default for Session {
-    fn default() -> Self {
-        Self {
+impl Session {
+    /// Session of `app_config` with nothing searched, pinned or annotated yet.
+    pub fn new(app_config: AppConfig, client: http::Client) -> Self {
+        Session {
             last_search: None,
-            app_config: AppConfig::default()
+            app_config,
+            annotations: Default::default(),
+            history: Default::default(),
+            pinned: vec![],
+            name: None,
+            view: String::new(),
+            layout: None,
+            client,
+            verbose: false,
         }
     }
+
+    pub fn layout(&self) -> layout::Layout {
+        match self.layout {
+            Some(layout) => layout,
+            None if self.app_config.screen_reader => layout::Layout::Labelled,
+            None => self.app_config.layout,
+        }
+    }
+
+    pub fn view(&self) -> views::View {
+        let name = if self.view.is_empty() { &self.app_config.view } else { &self.view };
+        views::View::load(name, &self.app_config)
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -136,33 +252,86 @@ pub struct SearchResultsRaw {
     pub result_count: i32,
 }
 
-#[derive(StructOpt, Debug, Clone, Serialize, Deserialize)]
+#[derive(StructOpt, Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
-struct Params {
+pub struct Params {
     #[structopt(default_value = "")]
     lookfor: Vec<String>,
 
     #[structopt(long = "type", short = "t", default_value = "AllFields")]
     r#type: String,
 
+    /// Filter (field:value), excluded (-field:value) or OR'ed (~field:value)
     #[structopt(long, short)]
-    filter: Option<Vec<String>>,
+    filter: Option<Vec<filters::Filter>>,
+    #[structopt(long)]
+    sort: Option<String>,
     #[structopt(long, short, default_value = "20")]
     limit: i32,
     #[structopt(long, short, default_value = "1")]
     page: i32,
-    #[structopt(long, default_value = "fi")]
-    lng: String,
+    /// Language of the results: fi, sv or en-gb [default: fi]
+    #[structopt(long)]
+    lng: Option<String>,
 
     #[structopt(long, default_value = "[]")]
     field: Vec<String>,
+
+    /// Records located within lon1,lat1,lon2,lat2
+    #[structopt(long, allow_hyphen_values = true)]
+    #[serde(skip)]
+    bbox: Option<geo::BoundingBox>,
+    /// Records located near lat,lon (see --radius)
+    #[structopt(long, allow_hyphen_values = true)]
+    #[serde(skip)]
+    near: Option<geo::Point>,
+    /// Distance in kilometres from --near [default: 10]
+    #[structopt(long)]
+    #[serde(skip)]
+    radius: Option<f64>,
+}
+impl Params {
+    /// Language of the results, `fi` unless given.
+    pub fn lng(&self) -> &str {
+        self.lng.as_deref().unwrap_or(DEFAULT_LNG)
+    }
 }
 
-#[derive(StructOpt, Debug, Clone, Serialize, Deserialize)]
-struct RecordParams {
-    #[structopt(default_value = "")]
-    id: Vec<String>,
-    field: Vec<String>,
+#[derive(StructOpt, Debug)]
+struct Opts {
+    #[structopt(flatten)]
+    params: Params,
+
+    /// Restore a saved session (and save it again on exit)
+    #[structopt(long)]
+    session: Option<String>,
+
+    /// Print the records listed in a file (one id per line, "-" for stdin) and exit
+    #[structopt(long, parse(from_os_str))]
+    ids_file: Option<PathBuf>,
+    /// Output format of --ids-file: text, json, raw or full
+    #[structopt(long, default_value = "json")]
+    format: RecordQuery,
+
+    /// Colour output: auto, always or never
+    #[structopt(long)]
+    color: Option<theme::ColorMode>,
+    /// Labelled results without colours, for screen readers
+    #[structopt(long)]
+    screen_reader: bool,
+    /// Print the url of each API request
+    #[structopt(long, short)]
+    verbose: bool,
+
+    /// Save every API request and response to a directory
+    #[structopt(long, parse(from_os_str), conflicts_with = "replay-traffic")]
+    record_traffic: Option<PathBuf>,
+    /// Answer API requests with the responses saved with --record-traffic instead of the network
+    #[structopt(long, parse(from_os_str), conflicts_with = "local")]
+    replay_traffic: Option<PathBuf>,
+    /// Search harvested records (a JSON Lines file) or the responses saved with --record-traffic (a directory) offline
+    #[structopt(long, parse(from_os_str), conflicts_with = "record-traffic")]
+    local: Option<PathBuf>,
 }
 
 #[derive(Helper)]
@@ -206,7 +375,7 @@ impl Highlighter for MyHelper {
     }
 
     fn highlight_hint<'h>(&self, hint: &'h str) -> Cow<'h, str> {
-        Owned("\x1b[1m".to_owned() + hint + "\x1b[m")
+        Owned(theme::paint("hint", hint).to_string())
     }
 
     fn highlight<'l>(&self, line: &'l str, pos: usize) -> Cow<'l, str> {
@@ -218,94 +387,142 @@ impl Highlighter for MyHelper {
     }
 }
 
-fn vec2str(vec: &Vec<String>, delimiter: &str) -> String {
-    if vec.len() == 1 {
-        return (vec[0].clone().trim()).to_string();
-    } else {
-        let tot = vec.len();
-        vec.iter()
-            .enumerate()
-            .fold(String::new(), |acc, (i, arg)| {
-                let mut res = acc + &arg;
-                if i < tot - 1 {
-                    res = res + delimiter;
-                }
-                res.to_string()
-            })
-            .trim()
-            .to_string()
+fn vec2str(vec: &[String], delimiter: &str) -> String {
+    vec.join(delimiter).trim().to_string()
+}
+
+fn view_result(rec: &Record, cnt: usize, view: &views::View, single_line: bool, annotations: &annotations::Annotations) {
+    let width = layout::terminal_width();
+    println!("{}", view.format(rec, cnt, width, single_line));
+    if let Some(annotation) = rec.id.as_ref().and_then(|id| annotations.get(id)) {
+        println!("{}", annotations::format_annotation(annotation, width));
     }
 }
 
-fn view_result(rec: &Record, cnt: usize) {
-    let (format, format_code) = match rec.formats.clone().pop() {
-        Some(format) => (format.translated, format.value),
-        None => ("?".to_string(), "?".to_string()),
-    };
+fn view_records(results: &SearchResults, session: &Session) {
+    let view = session.view();
+    match session.layout() {
+        layout::Layout::Table => {
+            println!(
+                "{}",
+                layout::format_table(&results.records, &session.annotations, layout::terminal_width())
+            );
+        }
+        layout::Layout::Labelled => {
+            for (i, rec) in results.records.iter().enumerate() {
+                let annotation = rec.id.as_ref().and_then(|id| session.annotations.get(id));
+                println!("{}", layout::format_labelled(rec, i, annotation));
+            }
+        }
+        layout => {
+            for (i, rec) in results.records.iter().enumerate() {
+                view_result(rec, i, &view, layout == layout::Layout::Compact, &session.annotations);
+            }
+        }
+    }
+}
 
-    let authors_to_str = |authors:&Vec<Author>| -> Vec<String> {
-        authors.iter().map(|p| p.name.clone()).collect()
-    };
-    
-    let authors = if rec.primary_authors.len() > 0 {
-        rec.primary_authors.clone()
-    } else if rec.non_presenter_authors.len() > 0 {
-        authors_to_str(&rec.non_presenter_authors).clone()
-    } else {
-        vec![]
-    };
-               
-    let mut building = "";
-    if rec.buildings.len() > 0 {
-        building = &rec.buildings.first().unwrap().translated;
-    }
-    let year = match &rec.year {
-        Some(year) => format!(" ({})", year.clone()),
-        None => "".into()
-    };
-    println!(
-        "{cnt:>3} {title:.len$}{year}  {format} - {format_code}",
-        cnt = (cnt + 1).to_string().yellow(),
-        title = rec.title.as_ref().unwrap().bold(),
-        year = year,
-        format = format.yellow(),
-        format_code = format_code,
-        len = 80
-    );
-    println!("{fill:>4}{authors}  {building}",
-             fill = "",
-             building = building.blue(),
-             authors = vec2str(&authors, " | ")
-    );
+fn view_results(params: &Params, results: &SearchResults, session: &Session) {
+    view_records(results, session);
+    println!("\n{}", format_search_summary(params, results));
 }
 
-fn view_results(params: &Params, results: &SearchResults) {
-    for (i, rec) in results.records.iter().enumerate() {
-        view_result(&rec, i);
-    }
-    println!(
-        "\n{lookfor} ({results} {results_label}, page {page}){filters}",
-        lookfor = format!("{}", vec2str(&params.lookfor, " ").yellow().bold()),
-        results_label = format!("{}", "results"),
-        results = results.result_count.to_string(),
-        page = params.page,
+fn format_search_summary(params: &Params, results: &SearchResults) -> String {
+    format!(
+        "{lookfor} ({results}){filters}",
+        lookfor = theme::paint("lookfor", &vec2str(&params.lookfor, " ")),
+        results = tr!("{} results, page {}", results.result_count, params.page),
         filters = if let Some(filters) = &params.filter {
-            format!(", filter: {:?}", filters)
+            format!(", {}", filters::format_summary(filters, &results.records))
         } else {
             "".to_string()
         }
-    );
+    )
 }
 
+#[derive(Debug)]
 enum RecordQuery {
+    Details,
     Fields,
     RawData,
     FullRecord,
 }
+impl RecordQuery {
+    fn fields(&self) -> Vec<String> {
+        match self {
+            RecordQuery::Details | RecordQuery::Fields => details::detail_fields(),
+            RecordQuery::RawData => vec!["rawData".to_string()],
+            RecordQuery::FullRecord => vec!["fullRecord".to_string()],
+        }
+    }
+}
+impl FromStr for RecordQuery {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(RecordQuery::Details),
+            "json" => Ok(RecordQuery::Fields),
+            "raw" => Ok(RecordQuery::RawData),
+            "full" => Ok(RecordQuery::FullRecord),
+            _ => Err(tr!("Unknown format: {} (expected text, json, raw or full)", s)),
+        }
+    }
+}
+
+const DEFAULT_LNG: &str = "fi";
+
+/// Words that run a subcommand instead of a search when given first.
+const SUBCOMMANDS: [&str; 3] = ["check", "harvest", "oai"];
+
+/// Number of ids per `/record` request in batch mode.
+const RECORD_BATCH_SIZE: usize = 50;
+
+/// Print the records listed in a file, one per line (JSON Lines for json and raw).
+fn batch_records(path: &Path, query_type: &RecordQuery, session: &mut Session) {
+    let input = if path == Path::new("-") {
+        let mut input = String::new();
+        io::stdin().read_to_string(&mut input).map(|_| input)
+    } else {
+        fs::read_to_string(path)
+    };
+    let ids: Vec<String> = match input {
+        Ok(input) => input
+            .lines()
+            .map(str::trim)
+            .filter(|id| !id.is_empty() && !id.starts_with('#'))
+            .map(String::from)
+            .collect(),
+        Err(e) => {
+            error(&tr!("Error reading ids: {}", e));
+            return;
+        }
+    };
+
+    // Concurrent batches, printed in the order of the file as each group is done
+    let fields = query_type.fields();
+    let group_size = RECORD_BATCH_SIZE * session.app_config.http.concurrency.max(1);
+    let mut found = 0;
+    for group in ids.chunks(group_size) {
+        let urls: Vec<String> = group.chunks(RECORD_BATCH_SIZE).map(|batch| record_url(batch, &fields, session)).collect();
+        let responses = match call_api_all(&urls, session) {
+            Some(responses) => responses,
+            None => break,
+        };
+        for response in responses.iter().flatten() {
+            let records = format_records(query_type, response, false, session).unwrap_or_default();
+            found += records.len();
+            for data in records {
+                println!("{}", data);
+            }
+        }
+    }
+    debug(&tr!("Fetched {}/{} records", found, ids.len()));
+}
 fn record_view_raw(id: &str, session: &mut Session) {
     record(
         RecordQuery::RawData,
-        &id,
+        id,
         vec!["rawData".to_string()],
         session,
     )
@@ -313,93 +530,203 @@ fn record_view_raw(id: &str, session: &mut Session) {
 fn record_view_full_record(id: &str, session: &mut Session) {
     record(
         RecordQuery::FullRecord,
-        &id,
+        id,
         vec!["fullRecord".to_string()],
         session,
     )
 }
 
 fn record_view(id: &str, session: &mut Session) {
+    record(
+        RecordQuery::Details,
+        id,
+        details::detail_fields(),
+        session,
+    )
+}
+
+fn record_view_json(id: &str, session: &mut Session) {
     record(
         RecordQuery::Fields,
-        &id,
-        rec_fields(),
+        id,
+        details::detail_fields(),
         session,
     )
 }
 
-fn call_api(url: &str, _session: &mut Session) -> Option<reqwest::Response> {
-    debug(&url);
-    match reqwest::get(url) {
+fn call_api(url: &str, session: &mut Session) -> Option<http::Response> {
+    if session.verbose {
+        debug(url);
+    }
+    let result = session.client.get(url);
+    api_response(result)
+}
+
+/// Call the API with many urls, `http.concurrency` at a time. The responses
+/// are in the order of the urls, None for the failed ones. None if cancelled.
+fn call_api_all(urls: &[String], session: &mut Session) -> Option<Vec<Option<http::Response>>> {
+    if session.verbose {
+        for url in urls {
+            debug(url);
+        }
+    }
+    match session.client.get_all(urls) {
+        Ok(results) => Some(results.into_iter().map(api_response).collect()),
+        Err(e) => {
+            error(&e);
+            None
+        }
+    }
+}
+
+/// Successful response, or an error printed.
+fn api_response(result: Result<http::Response, String>) -> Option<http::Response> {
+    match result {
+        Ok(response) if response.is_success() => Some(response),
         Ok(response) => {
-            if response.status().is_success() {
-                return Some(response);
+            debug(&format!("HTTP {}", response.status));
+            error(t("Network error"));
+            None
+        }
+        Err(e) => {
+            if cancel::cancelled() {
+                error(&e);
+            } else {
+                debug(&e);
+                error(t("Network error"));
             }
+            None
         }
-        _ => {
-            return None;
+    }
+}
+/// Results of a response, or None with an error printed if they can't be parsed.
+fn parse_response<T: serde::de::DeserializeOwned>(response: &http::Response) -> Option<T> {
+    match response.json() {
+        Ok(results) => Some(results),
+        Err(e) => {
+            error(&tr!("Error parsing results: {}", e));
+            None
         }
     }
-    return None;
 }
 fn debug(msg: &str) {
-    println!("\n{}\n", msg.dimmed());
+    eprintln!("\n{}\n", theme::paint_stderr("debug", msg));
 }
 fn error(msg: &str) {
-    println!("\n{}\n", msg.red().bold());
+    eprintln!("\n{}\n", theme::paint_stderr("error", msg));
 }
 
 fn record(query_type: RecordQuery, id: &str, fields: Vec<String>, session: &mut Session) {
-    let params = RecordParams {
-        id: vec![id.to_string()],
-        field: fields,
-    };
-    let query = serde_qs::to_string(&params);
-    match query {
-        Ok(query) => {
-            let url = session.app_config.api_url.to_owned() + &"/record?" + &query;
-            match call_api(&url, session) {
-                Some(mut response) => {
-                    match query_type {
-                        RecordQuery::Fields => {
-                            let results: SearchResults = response.json().expect("Error parsing results");
-                            println!("{}", serde_json::to_string_pretty(&results.records[0]).unwrap());
-                        }
-                        RecordQuery::FullRecord => {
-                            let results: SearchResultsFull = response.json().expect("Error parsing results");
-                            let mut data = serde_json::to_string(&results.records[0].full_record).unwrap();
+    if let Some(records) = fetch_records(&query_type, &[id.to_string()], fields, true, session) {
+        match records.first() {
+            Some(data) => println!("{}", data),
+            None => error(t("Record not found")),
+        }
+    }
+}
 
-                            // Clean up
-                            data = data.replace("\\n", "")
-                                .replace("\"", &'"'.to_string()).replace("\\", "");
-                            data = data[1..data.len()-1].to_string();
+fn record_url(ids: &[String], fields: &[String], session: &Session) -> String {
+    format!("{}/record?{}", session.app_config.api_url, query::record_query(ids, fields))
+}
 
-                            // Add line breaks between tags, preserve indentation
-                            let regex = Regex::new(r">(?P<indent>\s*)<").unwrap();
-                            data = regex.replace_all(&data.to_string(), ">\n$indent<").to_mut().to_string();
+/// Fetch records by id and format them according to `query_type`.
+fn fetch_records(
+    query_type: &RecordQuery,
+    ids: &[String],
+    fields: Vec<String>,
+    pretty: bool,
+    session: &mut Session,
+) -> Option<Vec<String>> {
+    let response = call_api(&record_url(ids, &fields, session), session)?;
+    format_records(query_type, &response, pretty, session)
+}
 
-                            println!("{}", data);
-                        }
-                        RecordQuery::RawData => {
-                            let results: SearchResultsRaw = response.json().expect("Error parsing results");
-                            println!(
-                                "{}",
-                                serde_json::to_string_pretty(&results.records[0].raw_data).unwrap()
-                            );
-                        }
-                    }
-                }
-                _ => {
-                    error("Network error");
-                }
-            }
+/// Format the records of a response according to `query_type`. None if the
+/// response can't be parsed.
+fn format_records(query_type: &RecordQuery, response: &http::Response, pretty: bool, session: &Session) -> Option<Vec<String>> {
+    Some(match query_type {
+        RecordQuery::Details => {
+            let results: SearchResults = parse_response(response)?;
+            results
+                .records
+                .iter()
+                .map(|rec| details::format_record(rec, session.app_config.screen_reader))
+                .collect()
+        }
+        RecordQuery::Fields => {
+            let results: SearchResults = parse_response(response)?;
+            results.records.iter().map(|rec| to_json(rec, pretty)).collect()
+        }
+        RecordQuery::FullRecord => {
+            let results: SearchResultsFull = parse_response(response)?;
+            results.records.iter().map(|rec| format_full_record(&rec.full_record)).collect()
         }
-        _ => {
-            error("Invalid url");
+        RecordQuery::RawData => {
+            let results: SearchResultsRaw = parse_response(response)?;
+            results.records.iter().map(|rec| to_json(&rec.raw_data, pretty)).collect()
         }
+    })
+}
+
+/// Fetch records by id with the given fields, in concurrent batches.
+fn lookup_records(ids: &[String], fields: Vec<String>, session: &mut Session) -> Option<Vec<Record>> {
+    let urls: Vec<String> = ids.chunks(RECORD_BATCH_SIZE).map(|batch| record_url(batch, &fields, session)).collect();
+    let mut records = vec![];
+    for response in call_api_all(&urls, session)? {
+        let results: SearchResults = parse_response(&response?)?;
+        records.extend(results.records);
+    }
+    Some(records)
+}
+
+/// Map of the locations of the records, fetched unless the view includes them.
+fn view_map(results: &SearchResults, session: &mut Session) {
+    let located;
+    let records = if geo::has_locations(&results.records) {
+        &results.records
+    } else {
+        let ids: Vec<String> = results.records.iter().filter_map(|rec| rec.id.clone()).collect();
+        let fields = vec!["id".to_string(), "title".to_string(), geo::LOCATION_RECORD_FIELD.to_string()];
+        let fetched = match lookup_records(&ids, fields, session) {
+            Some(records) => records,
+            None => return,
+        };
+        // In the order of the result page, so that the numbers match
+        located = results
+            .records
+            .iter()
+            .map(|rec| fetched.iter().find(|found| found.id == rec.id).unwrap_or(rec).clone())
+            .collect::<Vec<Record>>();
+        &located
+    };
+    println!(
+        "{}",
+        geo::format_map(records, layout::terminal_width(), session.app_config.screen_reader)
+    );
+}
+
+fn to_json<T: serde::Serialize>(value: &T, pretty: bool) -> String {
+    if pretty {
+        serde_json::to_string_pretty(value).unwrap()
+    } else {
+        serde_json::to_string(value).unwrap()
     }
 }
 
+/// Format original metadata (usually XML) for display.
+fn format_full_record(full_record: &str) -> String {
+    let mut data = serde_json::to_string(full_record).unwrap();
+
+    // Clean up
+    data = data.replace("\\n", "")
+        .replace("\"", &'"'.to_string()).replace("\\", "");
+    data = data[1..data.len()-1].to_string();
+
+    // Add line breaks between tags, preserve indentation
+    let regex = Regex::new(r">(?P<indent>\s*)<").unwrap();
+    regex.replace_all(&data, ">\n$indent<").to_string()
+}
+
 fn rec_fields() -> Vec<String> {
     vec!["id".into(),
          "title".into(),
@@ -412,35 +739,130 @@ fn rec_fields() -> Vec<String> {
     ]
 }
 
-fn search(mut params: Params, session: &mut Session) -> Option<SearchResults> {
-    let params_copy = params.clone();
-    params.field = rec_fields();
-    let lookfor = &vec2str(&params.lookfor, " ");
-    params.lookfor = vec![];
-
-    let query = serde_qs::to_string(&params);
-    match query {
-        Ok(mut query) => {
-            query = query + "&lookfor=" + &lookfor;
-            let url = session.app_config.api_url.to_owned() + &"/search?" + &query;
-            session.last_search = Some(query);
-            match call_api(&url, session) {
-                Some(mut response) => {
-                    let results: SearchResults = response.json().expect("Error parsing results");
-                    view_results(&params_copy, &results);
-                    return Some(results);
-                }
-                _ => {
-                    error("Network error");
-                }
+/// Query of a search with the fields of the current view.
+fn search_query(params: &Params, session: &Session) -> String {
+    let mut params = params.clone();
+    params.field = session.view().fields;
+    query::search_query(&params).to_string()
+}
+
+fn fetch_results(params: Params, session: &mut Session) -> Option<SearchResults> {
+    let query = search_query(&params, session);
+    let url = format!("{}/search?{}", session.app_config.api_url, query);
+    session.last_search = Some(query);
+    parse_response(&call_api(&url, session)?)
+}
+
+/// Fetch result pages concurrently. None if any of them fails.
+fn fetch_pages(params: &Params, pages: RangeInclusive<i32>, session: &mut Session) -> Option<Vec<SearchResults>> {
+    let urls: Vec<String> = pages
+        .map(|page| {
+            let params = Params { page, ..params.clone() };
+            format!("{}/search?{}", session.app_config.api_url, search_query(&params, session))
+        })
+        .collect();
+    call_api_all(&urls, session)?
+        .into_iter()
+        .map(|response| parse_response(&response?))
+        .collect()
+}
+
+/// Look up a record of the current result page by its number.
+fn result_record<'a>(results: &'a SearchResults, num: &str) -> Option<&'a Record> {
+    match num.parse::<usize>() {
+        Ok(num) => match num.checked_sub(1).and_then(|i| results.records.get(i)) {
+            Some(rec) => Some(rec),
+            None => {
+                error(t("Invalid record number"));
+                None
             }
+        },
+        Err(_e) => {
+            error(t("Invalid record number"));
+            None
+        }
+    }
+}
+
+fn search_notes(query: &str, session: &Session) -> SearchResults {
+    let results = annotations::search(query, &session.annotations);
+    view_records(&results, session);
+    println!(
+        "\n{label}{query} ({results})",
+        label = theme::paint("name", t("notes")),
+        query = if query.is_empty() { "".to_string() } else { format!(": {}", query) },
+        results = tr!("{} results", results.result_count)
+    );
+    results
+}
+
+fn view_pinned(session: &Session) -> SearchResults {
+    let results = SearchResults {
+        result_count: session.pinned.len() as i32,
+        records: session.pinned.clone(),
+    };
+    view_records(&results, session);
+    println!("\n{} ({})", theme::paint("name", t("pinned")), tr!("{} results", results.result_count));
+    results
+}
+
+fn search(params: Params, session: &mut Session) -> Option<SearchResults> {
+    if session.app_config.ui_lng.is_none() {
+        i18n::follow(params.lng());
+    }
+    let results = fetch_results(params.clone(), session)?;
+    view_results(&params, &results, session);
+    prefetch_next_page(&params, &results, session);
+    session.history.push(history::HistoryEntry {
+        params,
+        results: results.clone(),
+        query: session.last_search.clone(),
+    });
+    Some(results)
+}
+
+/// Start fetching the page after `results`, to be used if it is requested next
+/// with the same parameters.
+fn prefetch_next_page(params: &Params, results: &SearchResults, session: &Session) {
+    let shown = params.page.saturating_mul(params.limit);
+    if !session.app_config.prefetch || params.limit <= 0 || shown >= results.result_count {
+        return;
+    }
+    let next = Params {
+        page: params.page + 1,
+        ..params.clone()
+    };
+    let url = format!("{}/search?{}", session.app_config.api_url, search_query(&next, session));
+    session.client.prefetch(&url);
+}
+
+/// Show a search from the session history without fetching it again.
+fn restore_search(entry: Option<history::HistoryEntry>, session: &mut Session) -> Option<(Params, SearchResults)> {
+    match entry {
+        Some(entry) => {
+            view_results(&entry.params, &entry.results, session);
+            session.last_search = entry.query;
+            Some((entry.params, entry.results))
         }
-        _ => {
-            error("Invalid url");
+        None => {
+            error(t("No such search in history"));
+            None
         }
     }
-    None
 }
+
+/// Start an external viewer and reap it in the background once it exits.
+fn spawn_viewer(cmd: &mut Command) {
+    match cmd.spawn() {
+        Ok(mut child) => {
+            std::thread::spawn(move || child.wait());
+        }
+        Err(_) => {
+            error(t("Error opening external program"));
+        }
+    }
+}
+
 fn record_action(action: &str, id: &str, record:&Record, session: &mut Session) {
     let open_record = |holdings: bool| {
         let anchor = if holdings { "#tabnav"} else { "" };
@@ -448,8 +870,8 @@ fn record_action(action: &str, id: &str, record:&Record, session: &mut Session)
                           url = session.app_config.site_url,
                           id = id,
                           anchor = anchor);
-        if !open::that(rec_url).is_ok() {
-            error("Error opening external program");
+        if open::that(rec_url).is_err() {
+            error(t("Error opening external program"));
         }
     };
     
@@ -457,6 +879,9 @@ fn record_action(action: &str, id: &str, record:&Record, session: &mut Session)
         "s" => {
             record_view(id, session);
         }
+        "json" => {
+            record_view_json(id, session);
+        }
         "raw" => {
             record_view_raw(id, session);
         }
@@ -464,16 +889,17 @@ fn record_action(action: &str, id: &str, record:&Record, session: &mut Session)
             record_view_full_record(id, session);
         }
         "img" => {
-            if let Some(img) = record.images.get(0) {
+            if let Some(img) = record.images.first() {
                 let path = format!("https://finna.fi{}", img);
-                Command::new("feh")
-                    .arg("--auto-zoom")
-                    .arg("--fullscreen")                    
-                    .arg("--borderless")
-                    .arg(path)
-                    .spawn().expect("process failed to execute");
+                spawn_viewer(
+                    Command::new("feh")
+                        .arg("--auto-zoom")
+                        .arg("--fullscreen")
+                        .arg("--borderless")
+                        .arg(path)
+                );
             } else {
-                println!("No images");
+                println!("{}", t("No images"));
             }
         }
         "finna" => {
@@ -486,29 +912,145 @@ fn record_action(action: &str, id: &str, record:&Record, session: &mut Session)
     }
 }
 
-fn save_history(reader: &Editor<(MyHelper)>) {
+/// Client of the network, recording the API traffic if asked to, or a client
+/// replaying recorded traffic without retries or waiting.
+fn traffic_client(opts: &Opts, config: &http::HttpConfig) -> Result<http::Client, String> {
+    match (&opts.record_traffic, &opts.replay_traffic) {
+        (Some(dir), _) => {
+            let network = Box::new(http::ReqwestTransport::new(config)?);
+            let transport = http::RecordingTransport::new(network, dir)
+                .map_err(|e| tr!("Error opening traffic directory: {}", e))?;
+            Ok(http::Client::new(Box::new(transport), config))
+        }
+        (None, Some(dir)) => {
+            let transport = http::ReplayTransport::new(dir).map_err(|e| tr!("Error opening traffic directory: {}", e))?;
+            let config = http::HttpConfig {
+                retries: 0,
+                request_interval: 0,
+                ..config.clone()
+            };
+            Ok(http::Client::new(Box::new(transport), &config))
+        }
+        (None, None) => match &opts.local {
+            Some(path) => {
+                let index = local::LocalIndex::load(path).map_err(|e| tr!("Error reading local records: {}", e))?;
+                debug(&tr!("{} records in the local index", index.len()));
+                let config = http::HttpConfig {
+                    retries: 0,
+                    request_interval: 0,
+                    ..config.clone()
+                };
+                Ok(http::Client::new(Box::new(local::LocalTransport(index)), &config))
+            }
+            None => http::Client::connect(config),
+        },
+    }
+}
+
+/// Session of a subcommand, with a client of the network configured by `app_config`.
+fn network_session(app_config: &AppConfig) -> Result<Session, String> {
+    let client = http::Client::connect(&app_config.http)?;
+    Ok(Session::new(app_config.clone(), client))
+}
+
+/// Parameters of a search typed at the prompt. The language selected with
+/// --lng or :lng is kept unless given again.
+fn search_line_params(line: &str, previous: &Params) -> Params {
+    // Prefix with whitespace to preserve first argument
+    let mut params = Params::from_iter(format!(" {}", line.trim()).split(' '));
+    geo::add_location_filters(&mut params);
+    if params.lng.is_none() {
+        params.lng = previous.lng.clone();
+    }
+    params
+}
+
+/// Subcommand given as the first argument, if any. A search for one of the
+/// words goes after `--`, e.g. `finna -- harvest`.
+fn subcommand(mut args: impl Iterator<Item = String>) -> Option<String> {
+    args.nth(1).filter(|arg| SUBCOMMANDS.contains(&arg.as_str()))
+}
+
+fn save_history(reader: &Editor<MyHelper>) {
     reader.save_history("finna_history.txt").unwrap();
 }
 fn main() {
-    let app_config: AppConfig = confy::load("finna-term").unwrap_or_default();
-    println!("{:#?}", app_config);
+    let mut app_config: AppConfig = confy::load("finna-term").unwrap_or_default();
+
+    if let Some(subcommand) = subcommand(std::env::args()) {
+        if let Some(lang) = app_config.ui_lng.as_deref().and_then(|lng| lng.parse().ok()) {
+            i18n::set(lang);
+        }
+        theme::init(&app_config);
+        // Skip the binary name so that the subcommand takes its place
+        match subcommand.as_str() {
+            "check" => {
+                let opts = saved_searches::CheckOpts::from_iter(std::env::args().skip(1));
+                saved_searches::check(&opts, &app_config);
+            }
+            "harvest" => {
+                let opts = harvest::HarvestOpts::from_iter(std::env::args().skip(1));
+                cancel::init(false);
+                harvest::harvest(&opts, &app_config);
+            }
+            _ => {
+                let opts = oai::OaiOpts::from_iter(std::env::args().skip(1));
+                cancel::init(false);
+                oai::oai(&opts, &app_config);
+            }
+        }
+        return;
+    }
+
+    let opts = Opts::from_args();
+    if let Some(color) = opts.color {
+        app_config.color = color;
+    }
+    app_config.screen_reader |= opts.screen_reader;
+    match app_config.ui_lng.as_deref().unwrap_or(opts.params.lng()).parse() {
+        Ok(lang) => i18n::set(lang),
+        Err(e) => error(&e),
+    }
+    theme::init(&app_config);
+    cancel::init(!app_config.screen_reader);
+    let client = match traffic_client(&opts, &app_config.http) {
+        Ok(client) => client,
+        Err(e) => {
+            error(&e);
+            return;
+        }
+    };
+    if let Some(path) = &opts.ids_file {
+        let mut session = Session {
+            verbose: opts.verbose,
+            ..Session::new(app_config, client)
+        };
+        batch_records(path, &opts.format, &mut session);
+        return;
+    }
+
+    let mut session = Session {
+        annotations: annotations::load(),
+        verbose: opts.verbose,
+        ..Session::new(app_config, client)
+    };
 
-    let mut session = Session::default();
-    session.app_config = app_config;
-    
     let mut results = SearchResults {
         result_count: 0,
         records: [].to_vec(),
     };
 
-    let mut params = Params::from_args();
-    println!("p: {:?}", params);
-    
-    match search(params.clone(), &mut session) {
-        Some(res) => {
-            results = res;
-        }
-        None => {}
+    let mut params = opts.params;
+    geo::add_location_filters(&mut params);
+
+    let restored = match &opts.session {
+        Some(name) => sessions::start(name, &mut session),
+        None => None,
+    };
+    if let Some(restored) = restored {
+        (params, results) = restored;
+    } else if let Some(res) = search(params.clone(), &mut session) {
+        results = res;
     }
     let config = Config::builder()
         .history_ignore_space(true)
@@ -528,122 +1070,509 @@ fn main() {
     //let mut reader = Editor::<()>::new();
     reader.set_helper(Some(helper));
     
-    if let Err(_) = reader.load_history("finna_history.txt") {}
+    let _ = reader.load_history("finna_history.txt");
 
+    let regex = Regex::new(r"^:([a-z]+)(?: (.+))?$").unwrap();
     let mut count = 1;
     loop {
         let p = format!("{}> ", count);
-        reader.helper_mut().expect("No helper").colored_prompt = format!("\x1b[1;32m{}\x1b[0m", p);
+        reader.helper_mut().expect("No helper").colored_prompt = theme::paint("prompt", &p).to_string();
         let readline = reader.readline(&p);
         
         match readline {
             Ok(line) => {
-                count = count+1;
+                count += 1;
                 reader.add_history_entry(&line);
 
-                let regex = Regex::new(r"^:([a-z]+)( ([\w\.]+))?$").unwrap();
-
-                if regex.is_match(line.as_ref()) {
+                if let Some(cap) = regex.captures(line.trim_end()) {
                     // Parse colon command
-                    let cap = regex.captures(line.as_ref()).unwrap();
-                    let cmd = &cap[1].trim();
+                    let cmd = &cap[1];
 
-                    match cap.get(3) {
+                    match cap.get(2).map(|arg| arg.as_str().trim()) {
                         // command with argument
-                        Some(id) => {
-                            let rec_id = id.as_str();
-                            match rec_id.parse::<usize>() {
-                                Ok(num) => match results.records.get(num - 1) {
-                                    Some(rec) => {
-                                        let id = rec.id.as_ref().unwrap().to_string();
-                                        record_action(cmd, &id, rec, &mut session);
+                        Some(arg) => match cmd {
+                            "savesearch" => {
+                                saved_searches::save(arg, &params, &session);
+                            }
+                            "note" | "tag" => {
+                                let (num, text) = arg.split_once(' ').unwrap_or((arg, ""));
+                                if let Some(rec) = result_record(&results, num) {
+                                    annotations::annotate(cmd, rec, text.trim(), &mut session.annotations);
+                                }
+                            }
+                            "notes" => {
+                                results = search_notes(arg, &session);
+                            }
+                            "id" => {
+                                record_view(arg, &mut session);
+                            }
+                            "layout" => match arg.parse::<layout::Layout>() {
+                                Ok(layout) => {
+                                    session.layout = Some(layout);
+                                    view_results(&params, &results, &session);
+                                }
+                                Err(e) => error(&e),
+                            },
+                            "timeline" => {
+                                let bar = arg.parse::<usize>().ok().and_then(|num| num.checked_sub(1));
+                                if let Some(bars) = timeline::fetch_bars(&params, &mut session) {
+                                    match bar.and_then(|i| bars.get(i)).ok_or_else(|| t("Invalid bar number").to_string()) {
+                                        Ok(bar) => match timeline::apply_bar(bar, &mut params) {
+                                            Ok(()) => {
+                                                if let Some(res) = search(params.clone(), &mut session) {
+                                                    results = res;
+                                                }
+                                            }
+                                            Err(e) => error(&e),
+                                        },
+                                        Err(e) => error(&e),
                                     }
-                                    None => {
-                                        error("Invalid record number");
+                                }
+                            }
+                            "set" | "filter" | "lng" => {
+                                let changed = match cmd {
+                                    "set" => settings::set(arg, &mut params, &mut session),
+                                    "filter" => settings::filter(arg, &mut params),
+                                    _ => settings::set_lng(arg, &mut params, &mut session),
+                                };
+                                match changed {
+                                    Ok(()) => {
+                                        if let Some(res) = search(params.clone(), &mut session) {
+                                            results = res;
+                                        }
                                     }
-                                },
-                                Err(_e) => {
-                                    println!("Invalid record index");
+                                    Err(e) => error(&e),
                                 }
                             }
-                        }
+                            "theme" => match theme::set(arg, &session.app_config) {
+                                Ok(()) => view_results(&params, &results, &session),
+                                Err(e) => error(&e),
+                            },
+                            "view" => match views::View::exists(arg, &session.app_config) {
+                                Ok(()) => {
+                                    session.view = arg.to_string();
+                                    if let Some(res) = search(params.clone(), &mut session) {
+                                        results = res;
+                                    }
+                                }
+                                Err(e) => error(&e),
+                            },
+                            "pin" | "unpin" => {
+                                if let Some(rec) = result_record(&results, arg) {
+                                    if cmd == "pin" {
+                                        sessions::pin(rec, &mut session);
+                                    } else {
+                                        sessions::unpin(rec, &mut session);
+                                    }
+                                }
+                            }
+                            "session" => match arg.split_once(' ') {
+                                Some(("save", name)) => {
+                                    sessions::save(name.trim(), &params, &results, &mut session);
+                                }
+                                Some(("load", name)) => {
+                                    if let Some(restored) = sessions::load(name.trim(), &mut session) {
+                                        (params, results) = restored;
+                                    }
+                                }
+                                _ => {
+                                    error(t("Usage: :session save|load <name>"));
+                                }
+                            },
+                            "history" => {
+                                let entry = match arg.parse::<usize>() {
+                                    Ok(num) => num.checked_sub(1).and_then(|i| session.history.go(i)).cloned(),
+                                    Err(_e) => None,
+                                };
+                                if let Some(restored) = restore_search(entry, &mut session) {
+                                    (params, results) = restored;
+                                }
+                            }
+                            "download" => {
+                                images::download(&results.records, Path::new(arg), &mut session);
+                            }
+                            _ => {
+                                if let Some(rec) = result_record(&results, arg) {
+                                    let id = rec.id.as_ref().unwrap().to_string();
+                                    record_action(cmd, &id, rec, &mut session);
+                                }
+                            }
+                        },
                         None => {
                             // command without argument
-                            match cmd.as_ref() {
+                            match cmd {
                                 "q" => {
                                     save_history(&reader);
                                     break;
                                 }
                                 "n" => {
-                                    params.page = params.page + 1;
-                                    match search(params.clone(), &mut session) {
-                                        Some(res) => {
-                                            results = res;
-                                        }
-                                        None => {}
+                                    params.page += 1;
+                                    if let Some(res) = search(params.clone(), &mut session) {
+                                        results = res;
                                     }
                                 }
                                 "r" => {
-                                    search(params.clone(), &mut session);
+                                    if let Some(res) = search(params.clone(), &mut session) {
+                                        results = res;
+                                    }
+                                }
+                                "back" | "forward" => {
+                                    let entry = if cmd == "back" {
+                                        session.history.back()
+                                    } else {
+                                        session.history.forward()
+                                    }.cloned();
+                                    if let Some(restored) = restore_search(entry, &mut session) {
+                                        (params, results) = restored;
+                                    }
+                                }
+                                "history" => {
+                                    session.history.view();
+                                }
+                                "layout" => {
+                                    println!("{}", tr!("{} (lines, compact, table or labelled)", format!("{:?}", session.layout()).to_lowercase()));
+                                }
+                                "view" => {
+                                    for name in views::View::names(&session.app_config) {
+                                        println!("{}", name);
+                                    }
+                                }
+                                "lng" => {
+                                    println!("{}", params.lng());
+                                }
+                                "timeline" => {
+                                    if let Some(bars) = timeline::fetch_bars(&params, &mut session) {
+                                        let width = layout::terminal_width();
+                                        println!("{}", timeline::format_chart(&bars, width, session.app_config.screen_reader));
+                                    }
+                                }
+                                "map" => {
+                                    view_map(&results, &mut session);
+                                }
+                                "download" => {
+                                    images::download(&results.records, Path::new(images::DEFAULT_DIR), &mut session);
+                                }
+                                "set" | "show" => {
+                                    println!("{}", settings::format_params(&params));
+                                }
+                                "filter" => {
+                                    println!("{}", settings::format_filters(&params));
+                                }
+                                "theme" => {
+                                    for name in theme::names(&session.app_config) {
+                                        println!("{}", name);
+                                    }
+                                }
+                                "pins" => {
+                                    results = view_pinned(&session);
+                                }
+                                "session" => {
+                                    sessions::list();
+                                }
+                                "notes" => {
+                                    results = search_notes("", &session);
                                 }
                                 "finna" => {
-                                    match &session.last_search {
-                                        Some(query) => {
-                                            let site_url = format!(
-                                                "{url}/Search/Results?{query}",
-                                                url = session.app_config.site_url,
-                                                query = query
-                                            );
-                                            if !open::that(site_url).is_ok() {
-                                                error("Error opening external program");
-                                            }
+                                    if let Some(query) = &session.last_search {
+                                        let site_url = format!(
+                                            "{url}/Search/Results?{query}",
+                                            url = session.app_config.site_url,
+                                            query = query
+                                        );
+                                        if open::that(site_url).is_err() {
+                                            error(t("Error opening external program"));
                                         }
-                                        _ => {}
                                     };
                                 }
                                 "img" => {
-                                    //let imgs = Vec::<String>::new();
-                                    let mut imgs:Vec<String> = results.records.iter().map(|rec| rec.images.iter().map(|img| img.clone()).collect()).collect();
-                                    imgs = imgs.iter().map(|img| format!("https://finna.fi{}", img)).collect();
-                                    if imgs.len() > 0 {
+                                    let imgs: Vec<String> = results.records.iter()
+                                        .filter_map(|rec| rec.images.first())
+                                        .map(|img| format!("https://finna.fi{}", img))
+                                        .collect();
+                                    if !imgs.is_empty() {
                                         let mut cmd = Command::new("feh");
                                         cmd.arg("--auto-zoom")
-                                            .arg("--fullscreen")                    
+                                            .arg("--fullscreen")
                                             .arg("--borderless");
                                         for img in imgs {
                                             cmd.arg(img);
                                         }
-                                        cmd.spawn().expect("process failed to execute");
+                                        spawn_viewer(&mut cmd);
                                     } else {
-                                        println!("No images");
+                                        println!("{}", t("No images"));
                                     }
                                 }
                                 _ => {
-                                    error("Unknown command");
+                                    error(t("Unknown command"));
                                 }
                             }
                         }
                     }
-                } else {
-                    // Prefix with whitespace to preserve first argument
-                    params = Params::from_iter(format!(" {}", line.trim()).split(" "));
-                    match search(params.clone(), &mut session) {
-                        Some(res) => {
-                            results = res;
+                } else if line.trim().starts_with("http://") || line.trim().starts_with("https://") {
+                    match links::parse(&line) {
+                        Some(links::SiteLink::Search(link_params)) => {
+                            params = *link_params;
+                            if let Some(res) = search(params.clone(), &mut session) {
+                                results = res;
+                            }
                         }
-                        None => {}
+                        Some(links::SiteLink::Record(id)) => {
+                            record_view(&id, &mut session);
+                        }
+                        None => {
+                            error(t("Unsupported URL"));
+                        }
+                    }
+                } else {
+                    params = search_line_params(&line, &params);
+                    if let Some(res) = search(params.clone(), &mut session) {
+                        results = res;
                     }
                 }
             }
-            Err(ReadlineError::Interrupted) => {
+            // Ctrl-C clears the line
+            Err(ReadlineError::Interrupted) => {}
+            Err(ReadlineError::Eof) => {
                 save_history(&reader);
                 break;
             }
-            Err(ReadlineError::Eof) => break,
             Err(err) => {
-                println!("Error: {:?}", err);
+                println!("{}", tr!("Error: {}", format!("{:?}", err)));
                 break;
             }
         }
     }
+
+    if let Some(name) = session.name.clone() {
+        sessions::save(&name, &params, &results, &mut session);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mock_api::MockApi;
+
+    fn test_session(api: &MockApi) -> Session {
+        colored::control::set_override(false);
+        let app_config = AppConfig {
+            api_url: api.url.clone(),
+            ..AppConfig::default()
+        };
+        Session::new(app_config, api.client(&http::HttpConfig::default()))
+    }
+
+    fn params(args: &[&str]) -> Params {
+        Params::from_iter(std::iter::once("finna").chain(args.iter().copied()))
+    }
+
+    struct Unavailable;
+    impl http::Transport for Unavailable {
+        fn get(&self, _url: &str) -> http::ResponseFuture {
+            Box::new(futures::future::err("connection refused".to_string()))
+        }
+    }
+
+    #[test]
+    fn default_config() {
+        // Written by confy on the first run
+        toml::to_string(&AppConfig::default()).unwrap();
+    }
+
+    #[test]
+    fn keep_language() {
+        let previous = params(&["--lng", "sv", "kissa"]);
+        assert_eq!(search_line_params("koira", &previous).lng(), "sv");
+        assert_eq!(search_line_params("koira --lng=en-gb", &previous).lng(), "en-gb");
+        assert_eq!(search_line_params("koira --lng fi", &previous).lng(), "fi");
+        assert_eq!(search_line_params("koira", &params(&["kissa"])).lng(), "fi");
+    }
+
+    #[test]
+    fn subcommands() {
+        let args = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<String>>().into_iter();
+        assert_eq!(subcommand(args(&["finna", "harvest", "kissa"])).as_deref(), Some("harvest"));
+        assert_eq!(subcommand(args(&["finna", "kissa", "harvest"])), None);
+        // Searching for the word
+        assert_eq!(subcommand(args(&["finna", "--", "harvest"])), None);
+        assert_eq!(Opts::from_iter(args(&["finna", "--", "harvest"])).params.lookfor, ["harvest"]);
+    }
+
+    #[test]
+    fn search_results() {
+        let api = MockApi::start();
+        let mut session = test_session(&api);
+        let results = search(params(&["kissa"]), &mut session).unwrap();
+        assert_eq!(results.result_count, 45);
+        let titles: Vec<&str> = results.records.iter().filter_map(|rec| rec.title.as_deref()).collect();
+        assert_eq!(titles, ["Kissojen historia", "Kissa & koira: ystävykset", "Kissa ikkunalaudalla"]);
+        assert_eq!(results.records[1].non_presenter_authors[0].role.as_deref(), Some("kuvittaja"));
+
+        let requests = api.requests();
+        assert_eq!(requests.len(), 1);
+        assert!(requests[0].starts_with("/api/v1/search?"));
+        assert!(requests[0].contains("lookfor=kissa"));
+        assert!(requests[0].contains("field[0]=id"));
+        assert_eq!(session.last_search.as_deref(), requests[0].strip_prefix("/api/v1/search?"));
+    }
+
+    #[test]
+    fn search_pages() {
+        let api = MockApi::start();
+        let mut session = test_session(&api);
+        let mut params = params(&["kissa", "--limit", "3"]);
+        let first = fetch_results(params.clone(), &mut session).unwrap();
+        params.page += 1;
+        let second = fetch_results(params, &mut session).unwrap();
+        assert_eq!(first.records.len(), 3);
+        assert_eq!(second.records[0].id.as_deref(), Some("vaski.5678"));
+
+        let requests = api.requests();
+        assert!(requests[0].contains("limit=3&page=1"));
+        assert!(requests[1].contains("limit=3&page=2"));
+    }
+
+    #[test]
+    fn next_page_prefetched() {
+        let api = MockApi::start();
+        let mut session = test_session(&api);
+        let page_requests = |page: &str| api.requests().iter().filter(|req| req.contains(page)).count();
+        let mut params = params(&["kissa", "--limit", "3"]);
+        search(params.clone(), &mut session).unwrap();
+        params.page += 1;
+        let second = search(params.clone(), &mut session).unwrap();
+        assert_eq!(second.records[0].id.as_deref(), Some("vaski.5678"));
+        assert_eq!(page_requests("page=2"), 1);
+        // Not used after the sort changes
+        params.sort = Some("title".to_string());
+        search(params, &mut session).unwrap();
+        assert_eq!(page_requests("page=2"), 2);
+    }
+
+    #[test]
+    fn search_filters() {
+        let api = MockApi::start();
+        let mut session = test_session(&api);
+        let params = params(&["--filter=-format:0/Image/", "--filter", "~building:0/Helka/", "--", "kissa"]);
+        assert_eq!(
+            params.filter.as_ref().unwrap().iter().map(|filter| filter.to_string()).collect::<Vec<String>>(),
+            ["-format:\"0/Image/\"", "~building:\"0/Helka/\""]
+        );
+        let results = fetch_results(params, &mut session).unwrap();
+        assert_eq!(results.records.len(), 3);
+        let request = &api.requests()[0];
+        assert!(request.contains("filter[0]=-format%3A%220%2FImage%2F%22"), "{}", request);
+        assert!(request.contains("filter[1]=~building%3A%220%2FHelka%2F%22"), "{}", request);
+    }
+
+    #[test]
+    fn search_summary() {
+        let api = MockApi::start();
+        let mut session = test_session(&api);
+        let params = params(&["--filter=-format:0/Image/", "--filter", "building:0/Helka/", "--", "kissa"]);
+        let results = fetch_results(params.clone(), &mut session).unwrap();
+        assert_eq!(
+            format_search_summary(&params, &results),
+            "kissa (45 tulosta, sivu 1), Aineistotyyppi: ei Kuva; Organisaatio: Helsingin yliopiston kirjasto"
+        );
+    }
+
+    #[test]
+    fn record_details() {
+        let api = MockApi::start();
+        let mut session = test_session(&api);
+        let ids = ["satakirjastot.1234".to_string()];
+        let records = fetch_records(&RecordQuery::Details, &ids, details::detail_fields(), true, &mut session).unwrap();
+        assert_eq!(records.len(), 1);
+        assert!(records[0].starts_with("Kissa & koira: ystävykset\n"));
+        assert!(records[0].contains("Mäkinen, Pekka | Nieminen, Anna | Korhonen, Olli (kuvittaja)"));
+        assert!(records[0].contains("koirat : ystävyys"));
+        assert!(api.requests()[0].starts_with("/api/v1/record?id[0]=satakirjastot.1234&field[0]="));
+    }
+
+    #[test]
+    fn record_full() {
+        let api = MockApi::start();
+        let mut session = test_session(&api);
+        let ids = ["satakirjastot.1234".to_string()];
+        let records = fetch_records(&RecordQuery::FullRecord, &ids, RecordQuery::FullRecord.fields(), true, &mut session);
+        assert_eq!(
+            records.unwrap(),
+            ["<record>\n  <title>Kissa &amp; koira</title>\n  <date>2003</date>\n</record>"]
+        );
+    }
+
+    #[test]
+    fn labelled_results() {
+        let api = MockApi::start();
+        let mut session = test_session(&api);
+        let results = fetch_results(params(&["kissa"]), &mut session).unwrap();
+        assert_eq!(
+            layout::format_labelled(&results.records[1], 1, None),
+            "2. Nimeke: Kissa & koira: ystävykset; Vuosi: 2003; Aineistotyyppi: Kirja; \
+             Tekijät: Mäkinen, Pekka, Nieminen, Anna; Organisaatio: Satakirjastot"
+        );
+    }
+
+    #[test]
+    fn network_error() {
+        let config = http::HttpConfig {
+            retries: 0,
+            ..http::HttpConfig::default()
+        };
+        let mut session = Session::new(AppConfig::default(), http::Client::new(Box::new(Unavailable), &config));
+        assert!(fetch_results(params(&["kissa"]), &mut session).is_none());
+        assert!(lookup_records(&["x".to_string()], rec_fields(), &mut session).is_none());
+    }
+
+    #[test]
+    fn invalid_response() {
+        let api = MockApi::start();
+        let mut session = test_session(&api);
+        assert!(fetch_results(params(&["invalid"]), &mut session).is_none());
+        assert!(fetch_pages(&params(&["invalid"]), 2..=3, &mut session).is_none());
+        assert!(timeline::fetch_bars(&params(&["invalid"]), &mut session).is_none());
+    }
+
+    #[test]
+    fn search_pages_concurrently() {
+        let api = MockApi::start();
+        let mut session = test_session(&api);
+        let pages = fetch_pages(&params(&["kissa"]), 1..=3, &mut session).unwrap();
+        let first_ids: Vec<Option<&str>> = pages.iter().map(|results| results.records[0].id.as_deref()).collect();
+        assert_eq!(first_ids, [Some("helka.9910001234567890"), Some("vaski.5678"), Some("helka.9910001234567890")]);
+        let mut requested: Vec<String> = api.requests().iter().filter_map(|req| req.split("&page=").nth(1).map(|page| page[..1].to_string())).collect();
+        requested.sort();
+        assert_eq!(requested, ["1", "2", "3"]);
+    }
+
+    /// Time of fetching 1000 records in batches against a server with 200 ms
+    /// latency, by the number of concurrent requests. Run with
+    /// `cargo test --release bench_concurrency -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn bench_concurrency() {
+        let api = MockApi::with_latency(std::time::Duration::from_millis(200));
+        let ids: Vec<String> = (0..1000).map(|i| format!("bench.{}", i)).collect();
+        for (concurrency, request_interval) in [(1, 100), (2, 100), (4, 100), (8, 100), (4, 0), (8, 0)] {
+            let config = http::HttpConfig {
+                concurrency,
+                request_interval,
+                ..http::HttpConfig::default()
+            };
+            let mut session = test_session(&api);
+            session.client = api.client(&config);
+            let start = std::time::Instant::now();
+            lookup_records(&ids, rec_fields(), &mut session).unwrap();
+            println!("concurrency {}, interval {} ms: {:?}", concurrency, request_interval, start.elapsed());
+        }
+    }
+
+    #[test]
+    fn missing_endpoint() {
+        let api = MockApi::start();
+        let mut session = test_session(&api);
+        session.app_config.api_url += "/missing";
+        assert!(call_api(&format!("{}/nothing", session.app_config.api_url), &mut session).is_none());
+    }
 }
@@ -0,0 +1,141 @@
+//! Local stand-in for the Finna API in tests. Serves the JSON fixtures in
+//! `tests/fixtures`: `/search?...&page=2` is answered with `search-page2.json`
+//! if it exists and otherwise `search.json`, `/record` with `record.json`.
+//! `/search?lookfor=invalid` gets `search-invalid.json`, which isn't valid JSON.
+//! OAI-PMH requests are answered with `oai-<verb>.xml`, or with
+//! `oai-<verb>-<token>.xml` if they have a resumption token.
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use futures::future;
+
+use crate::http::{Client, HttpConfig, ReqwestTransport, ResponseFuture, Transport};
+
+pub struct MockApi {
+    /// API url to use as `AppConfig.api_url`
+    pub url: String,
+    requests: Arc<Mutex<Vec<String>>>,
+}
+
+impl MockApi {
+    /// Start a server on a free port. It runs until the tests exit.
+    pub fn start() -> MockApi {
+        MockApi::with_latency(Duration::from_millis(0))
+    }
+
+    /// Start a server answering each request after `latency`, like a remote
+    /// server. Requests are served concurrently.
+    pub fn with_latency(latency: Duration) -> MockApi {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Error starting mock API");
+        let url = format!("http://{}/api/v1", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(vec![]));
+        let log = requests.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let log = log.clone();
+                thread::spawn(move || {
+                    thread::sleep(latency);
+                    serve(stream, &log)
+                });
+            }
+        });
+        MockApi { url, requests }
+    }
+
+    /// Client of the server with the retries and limits of `config`. Requests
+    /// elsewhere fail, so that a test can't reach the network.
+    pub fn client(&self, config: &HttpConfig) -> Client {
+        let server = self.url.trim_end_matches("/api/v1").to_string();
+        let transport = ServerOnly {
+            inner: ReqwestTransport::new(config).expect("Error creating HTTP client"),
+            server,
+        };
+        Client::new(Box::new(transport), config)
+    }
+
+    /// Paths and query strings of the requests received so far.
+    pub fn requests(&self) -> Vec<String> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+/// Client for tests that make no requests: every request fails.
+pub fn offline_client() -> Client {
+    Client::new(Box::new(Offline), &HttpConfig::default())
+}
+
+struct Offline;
+impl Transport for Offline {
+    fn get(&self, url: &str) -> ResponseFuture {
+        Box::new(future::err(format!("No network in tests: {}", url)))
+    }
+}
+
+struct ServerOnly {
+    inner: ReqwestTransport,
+    server: String,
+}
+impl Transport for ServerOnly {
+    fn get(&self, url: &str) -> ResponseFuture {
+        if url.starts_with(&format!("{}/", self.server)) {
+            self.inner.get(url)
+        } else {
+            Offline.get(url)
+        }
+    }
+}
+
+fn fixture_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("fixtures")
+}
+
+/// Fixture file answering a request, by the endpoint and page of the request,
+/// or by the OAI-PMH verb and resumption token.
+fn fixture(path: &str) -> Option<PathBuf> {
+    let (endpoint, query) = path.split_once('?').unwrap_or((path, ""));
+    let name = endpoint.rsplit('/').next()?;
+    let param = |key: &str| query.split('&').find_map(|param| param.strip_prefix(key)?.strip_prefix('='));
+    let candidates = match (param("verb"), param("resumptionToken")) {
+        (Some(verb), Some(token)) => vec![format!("oai-{}-{}.xml", verb, token)],
+        (Some(verb), None) => vec![format!("oai-{}.xml", verb)],
+        (None, _) => param("lookfor")
+            .map(|lookfor| format!("{}-{}.json", name, lookfor))
+            .into_iter()
+            .chain(param("page").filter(|page| *page != "1").map(|page| format!("{}-page{}.json", name, page)))
+            .chain(Some(format!("{}.json", name)))
+            .collect(),
+    };
+    candidates.into_iter().map(|file| fixture_dir().join(file)).find(|file| file.exists())
+}
+
+/// Answer a request, logging it before the response so that the log is
+/// complete once the client has the response.
+fn serve(mut stream: TcpStream, log: &Mutex<Vec<String>>) -> Option<()> {
+    let mut reader = BufReader::new(stream.try_clone().ok()?);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).ok()?;
+    // Skip the headers
+    let mut header = String::new();
+    while reader.read_line(&mut header).ok()? > 2 {
+        header.clear();
+    }
+    let path = request_line.split_whitespace().nth(1)?.to_string();
+    log.lock().unwrap().push(path.clone());
+    let (status, body) = match fixture(&path).and_then(|file| std::fs::read_to_string(file).ok()) {
+        Some(body) => ("200 OK", body),
+        None => ("404 Not Found", "{\"status\":\"ERROR\"}".to_string()),
+    };
+    let _ = write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    Some(())
+}
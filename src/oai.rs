@@ -0,0 +1,467 @@
+//! OAI-PMH client for the `/OAI/Server` endpoint of VuFind sites: repository
+//! information, sets, metadata formats and harvesting records to files,
+//! following resumption tokens.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use chrono::{NaiveDate, NaiveDateTime};
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use structopt::StructOpt;
+
+use crate::query::Query;
+use crate::theme::{paint, paint_stderr};
+use crate::{call_api, error, format_full_record, network_session, AppConfig, Session};
+
+/// Path of the OAI-PMH endpoint of VuFind sites.
+const ENDPOINT_PATH: &str = "/OAI/Server";
+
+/// Harvest metadata from the OAI-PMH endpoint of the site
+#[derive(StructOpt, Debug)]
+pub struct OaiOpts {
+    /// Endpoint of the requests [default: the site url + /OAI/Server]
+    #[structopt(long)]
+    url: Option<String>,
+    #[structopt(subcommand)]
+    verb: Verb,
+}
+
+#[derive(StructOpt, Debug)]
+enum Verb {
+    /// Show information about the repository
+    #[structopt(name = "Identify")]
+    Identify,
+    /// List the sets the records are grouped in
+    #[structopt(name = "ListSets")]
+    ListSets,
+    /// List the metadata formats of the records
+    #[structopt(name = "ListMetadataFormats")]
+    ListMetadataFormats {
+        /// Formats of this record only
+        #[structopt(long)]
+        identifier: Option<String>,
+    },
+    /// Save records to a directory, one XML file per record
+    #[structopt(name = "ListRecords")]
+    ListRecords(ListRecordsOpts),
+}
+
+#[derive(StructOpt, Debug)]
+struct ListRecordsOpts {
+    /// Directory to save the records to
+    #[structopt(long, short, parse(from_os_str))]
+    output: PathBuf,
+    #[structopt(long, default_value = "oai_dc")]
+    metadata_prefix: String,
+    /// Records of a set only
+    #[structopt(long)]
+    set: Option<String>,
+    /// Records changed on or after a date (YYYY-MM-DD or YYYY-MM-DDThh:mm:ssZ)
+    #[structopt(long, parse(try_from_str = parse_date))]
+    from: Option<String>,
+    /// Records changed on or before a date (YYYY-MM-DD or YYYY-MM-DDThh:mm:ssZ)
+    #[structopt(long, parse(try_from_str = parse_date))]
+    until: Option<String>,
+    /// Continue an interrupted harvest from a resumption token
+    #[structopt(long)]
+    resumption_token: Option<String>,
+    /// Also print the records, formatted like `:full`
+    #[structopt(long)]
+    show: bool,
+}
+
+fn parse_date(s: &str) -> Result<String, String> {
+    let date = NaiveDate::parse_from_str(s, "%Y-%m-%d").is_ok();
+    if date || NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M:%SZ").is_ok() {
+        Ok(s.to_string())
+    } else {
+        Err(tr!("Invalid date: {} (expected YYYY-MM-DD or YYYY-MM-DDThh:mm:ssZ)", s))
+    }
+}
+
+/// An XML element, with the range of its content in the document.
+#[derive(Debug, Default)]
+struct Element {
+    /// Name without the namespace prefix
+    name: String,
+    attributes: HashMap<String, String>,
+    text: String,
+    children: Vec<Element>,
+    content: Range<usize>,
+}
+
+impl Element {
+    fn new(start: &BytesStart) -> Element {
+        let attributes = start
+            .attributes()
+            .flatten()
+            .map(|attr| {
+                let key = String::from_utf8_lossy(attr.key.local_name().as_ref()).into_owned();
+                let value = attr.unescape_value().map(|value| value.into_owned()).unwrap_or_default();
+                (key, value)
+            })
+            .collect();
+        Element {
+            name: String::from_utf8_lossy(start.local_name().as_ref()).into_owned(),
+            attributes,
+            ..Element::default()
+        }
+    }
+
+    fn child(&self, name: &str) -> Option<&Element> {
+        self.children.iter().find(|child| child.name == name)
+    }
+
+    fn children<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Element> {
+        self.children.iter().filter(move |child| child.name == name)
+    }
+
+    fn child_text(&self, name: &str) -> &str {
+        self.child(name).map_or("", |child| child.text.trim())
+    }
+}
+
+/// Parse an XML document to its root element.
+fn parse(xml: &str) -> Result<Element, String> {
+    let mut reader = Reader::from_str(xml);
+    let mut stack: Vec<Element> = vec![];
+    loop {
+        // Where the event starts, e.g. the start of an end tag
+        let start = reader.buffer_position();
+        let event = reader.read_event().map_err(|e| e.to_string())?;
+        let position = reader.buffer_position();
+        match event {
+            Event::Start(start) => {
+                let mut element = Element::new(&start);
+                element.content = position..position;
+                stack.push(element);
+            }
+            Event::Empty(start) => {
+                let element = Element::new(&start);
+                match stack.last_mut() {
+                    Some(parent) => parent.children.push(element),
+                    None => return Ok(element),
+                }
+            }
+            Event::Text(text) => {
+                if let Some(element) = stack.last_mut() {
+                    element.text += &text.unescape().map_err(|e| e.to_string())?;
+                }
+            }
+            Event::CData(text) => {
+                if let Some(element) = stack.last_mut() {
+                    element.text += &String::from_utf8_lossy(&text);
+                }
+            }
+            Event::End(_) => {
+                let mut element = stack.pop().ok_or("Unexpected end tag")?;
+                element.content.end = start;
+                match stack.last_mut() {
+                    Some(parent) => parent.children.push(element),
+                    None => return Ok(element),
+                }
+            }
+            Event::Eof => return Err("Unexpected end of document".to_string()),
+            _ => {}
+        }
+    }
+}
+
+/// A response of the endpoint.
+struct Response {
+    xml: String,
+    root: Element,
+}
+
+impl Response {
+    /// Element of the verb, e.g. `ListRecords`.
+    fn list(&self, verb: &str) -> Option<&Element> {
+        self.root.child(verb)
+    }
+
+    fn resumption_token(&self, verb: &str) -> Option<String> {
+        let token = self.list(verb)?.child_text("resumptionToken");
+        Some(token.to_string()).filter(|token| !token.is_empty())
+    }
+}
+
+/// Make a request. None if it fails or the response is an OAI-PMH error,
+/// except `noRecordsMatch`, which is an empty list.
+fn request(endpoint: &str, query: &Query, session: &mut Session) -> Option<Response> {
+    let url = format!("{}?{}", endpoint, query);
+    let response = call_api(&url, session)?;
+    let xml = String::from_utf8_lossy(&response.body).into_owned();
+    let root = match parse(&xml) {
+        Ok(root) => root,
+        Err(e) => {
+            error(&tr!("Error parsing OAI-PMH response: {}", e));
+            return None;
+        }
+    };
+    if let Some(oai_error) = root.child("error") {
+        let code = oai_error.attributes.get("code").map_or("", String::as_str);
+        if code != "noRecordsMatch" {
+            error(&tr!("OAI-PMH error {}: {}", code, oai_error.text.trim()));
+            return None;
+        }
+    }
+    Some(Response { xml, root })
+}
+
+fn verb_query(verb: &str) -> Query {
+    let mut query = Query::default();
+    query.push("verb", verb);
+    query
+}
+
+pub fn oai(opts: &OaiOpts, app_config: &AppConfig) {
+    let mut session = match network_session(app_config) {
+        Ok(session) => session,
+        Err(e) => {
+            error(&e);
+            return;
+        }
+    };
+    let endpoint = opts.url.clone().unwrap_or_else(|| format!("{}{}", app_config.site_url, ENDPOINT_PATH));
+    match &opts.verb {
+        Verb::Identify => identify(&endpoint, &mut session),
+        Verb::ListSets => list_sets(&endpoint, &mut session),
+        Verb::ListMetadataFormats { identifier } => list_metadata_formats(&endpoint, identifier.as_deref(), &mut session),
+        Verb::ListRecords(opts) => {
+            if let Some(harvested) = list_records(&endpoint, opts, &mut session) {
+                println!(
+                    "{}",
+                    tr!("Saved {} records to {}, {} deleted", harvested.saved, opts.output.display(), harvested.deleted)
+                );
+            }
+        }
+    }
+}
+
+fn identify(endpoint: &str, session: &mut Session) {
+    let response = match request(endpoint, &verb_query("Identify"), session) {
+        Some(response) => response,
+        None => return,
+    };
+    if let Some(identify) = response.list("Identify") {
+        // Descriptions are XML of their own
+        for field in identify.children.iter().filter(|field| field.children.is_empty()) {
+            println!("{:<20}{}", paint("label", &field.name), field.text.trim());
+        }
+    }
+}
+
+/// Request a list verb and the pages after it, calling `handle` with each page.
+fn list_pages(endpoint: &str, verb: &str, mut query: Query, session: &mut Session, mut handle: impl FnMut(&Element)) {
+    loop {
+        let response = match request(endpoint, &query, session) {
+            Some(response) => response,
+            None => return,
+        };
+        if let Some(list) = response.list(verb) {
+            handle(list);
+        }
+        match response.resumption_token(verb) {
+            Some(token) => {
+                query = verb_query(verb);
+                query.push("resumptionToken", &token);
+            }
+            None => return,
+        }
+    }
+}
+
+fn list_sets(endpoint: &str, session: &mut Session) {
+    list_pages(endpoint, "ListSets", verb_query("ListSets"), session, |list| {
+        for set in list.children("set") {
+            println!("{:<30}{}", paint("name", set.child_text("setSpec")), set.child_text("setName"));
+        }
+    });
+}
+
+fn list_metadata_formats(endpoint: &str, identifier: Option<&str>, session: &mut Session) {
+    let mut query = verb_query("ListMetadataFormats");
+    if let Some(identifier) = identifier {
+        query.push("identifier", identifier);
+    }
+    list_pages(endpoint, "ListMetadataFormats", query, session, |list| {
+        for format in list.children("metadataFormat") {
+            println!("{:<20}{}", paint("name", format.child_text("metadataPrefix")), format.child_text("schema"));
+        }
+    });
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct Harvested {
+    saved: usize,
+    deleted: usize,
+}
+
+/// Characters other than these are percent-encoded in file names.
+const FILE_NAME_ESCAPED: &AsciiSet = &NON_ALPHANUMERIC.remove(b'-').remove(b'_').remove(b'.');
+
+/// Record identifier as a file name, percent-encoded so that different
+/// identifiers never share a file, e.g. `oai:finna.fi:a/1` as `oai%3Afinna.fi%3Aa%2F1`.
+fn file_name(identifier: &str) -> String {
+    utf8_percent_encode(identifier, FILE_NAME_ESCAPED).to_string()
+}
+
+/// Save the records of a page to `dir`, and remove the deleted ones.
+fn save_records(response: &Response, dir: &Path, show: bool, harvested: &mut Harvested) -> io::Result<()> {
+    let records = response.list("ListRecords").into_iter().flat_map(|list| list.children("record"));
+    for rec in records {
+        let header = match rec.child("header") {
+            Some(header) => header,
+            None => continue,
+        };
+        let identifier = header.child_text("identifier");
+        let path = dir.join(format!("{}.xml", file_name(identifier)));
+        if header.attributes.get("status").map(String::as_str) == Some("deleted") {
+            match fs::remove_file(&path) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                _ => harvested.deleted += 1,
+            }
+            continue;
+        }
+        let metadata = match rec.child("metadata") {
+            Some(metadata) => response.xml[metadata.content.clone()].trim(),
+            None => continue,
+        };
+        fs::write(&path, metadata)?;
+        harvested.saved += 1;
+        if show {
+            println!("{}\n{}\n", paint("heading", identifier), format_full_record(metadata));
+        }
+    }
+    Ok(())
+}
+
+fn list_records(endpoint: &str, opts: &ListRecordsOpts, session: &mut Session) -> Option<Harvested> {
+    if let Err(e) = fs::create_dir_all(&opts.output) {
+        error(&tr!("Error writing output: {}", e));
+        return None;
+    }
+    let mut query = verb_query("ListRecords");
+    match &opts.resumption_token {
+        // The token stands for the other arguments
+        Some(token) => {
+            query.push("resumptionToken", token);
+        }
+        None => {
+            query.push("metadataPrefix", &opts.metadata_prefix);
+            for (key, value) in [("set", &opts.set), ("from", &opts.from), ("until", &opts.until)] {
+                if let Some(value) = value {
+                    query.push(key, value);
+                }
+            }
+        }
+    }
+
+    let mut harvested = Harvested::default();
+    let mut token = opts.resumption_token.clone();
+    loop {
+        let response = match request(endpoint, &query, session) {
+            Some(response) => response,
+            None => {
+                if let Some(token) = token {
+                    error(&tr!("Harvest interrupted, continue with --resumption-token {}", token));
+                }
+                return None;
+            }
+        };
+        if let Err(e) = save_records(&response, &opts.output, opts.show, &mut harvested) {
+            error(&tr!("Error writing output: {}", e));
+            return None;
+        }
+        token = response.resumption_token("ListRecords");
+        match &token {
+            Some(token) => {
+                eprintln!("{}", paint_stderr("debug", &tr!("{} records", harvested.saved + harvested.deleted)));
+                query = verb_query("ListRecords");
+                query.push("resumptionToken", token);
+            }
+            None => return Some(harvested),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http;
+    use crate::mock_api::MockApi;
+
+    fn session(api: &MockApi) -> (Session, String) {
+        colored::control::set_override(false);
+        let session = Session::new(AppConfig::default(), api.client(&http::HttpConfig::default()));
+        (session, api.url.replace("/api/v1", ENDPOINT_PATH))
+    }
+
+    #[test]
+    fn parse_xml() {
+        let xml = "<a x=\"1 &amp; 2\"><p:b>text &lt;</p:b><c/><d><![CDATA[<e>]]></d></a>";
+        let root = parse(xml).unwrap();
+        assert_eq!(root.attributes["x"], "1 & 2");
+        assert_eq!(root.child_text("b"), "text <");
+        assert!(root.child("c").is_some());
+        assert_eq!(root.child_text("d"), "<e>");
+        assert_eq!(&xml[root.child("b").unwrap().content.clone()], "text &lt;");
+        let xml = "<a><b>text</b ><c>more</c\n></a>";
+        let root = parse(xml).unwrap();
+        assert_eq!(&xml[root.child("b").unwrap().content.clone()], "text");
+        assert_eq!(&xml[root.child("c").unwrap().content.clone()], "more");
+        assert!(parse("<a><b></a>").is_err());
+    }
+
+    #[test]
+    fn file_names() {
+        assert_eq!(file_name("oai:finna.fi:helka.123"), "oai%3Afinna.fi%3Ahelka.123");
+        assert_ne!(file_name("a/b"), file_name("a_b"));
+        assert_ne!(file_name("a%2Fb"), file_name("a/b"));
+    }
+
+    #[test]
+    fn dates() {
+        assert!(parse_date("2026-10-01").is_ok());
+        assert!(parse_date("2026-10-01T08:00:00Z").is_ok());
+        assert!(parse_date("2026-13-01").is_err());
+        assert!(parse_date("1.10.2026").is_err());
+    }
+
+    #[test]
+    fn identify_and_errors() {
+        let api = MockApi::start();
+        let (mut session, endpoint) = session(&api);
+        let response = request(&endpoint, &verb_query("Identify"), &mut session).unwrap();
+        assert_eq!(response.list("Identify").unwrap().child_text("repositoryName"), "Finna");
+        assert!(request(&endpoint, &verb_query("ListSets"), &mut session).is_none());
+    }
+
+    #[test]
+    fn records() {
+        let api = MockApi::start();
+        let (mut session, endpoint) = session(&api);
+        let dir = std::env::temp_dir().join(format!("finna-oai-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("oai%3Afinna.fi%3Avaski.5678.xml"), "<old/>").unwrap();
+        let opts = ListRecordsOpts::from_iter(["ListRecords", "--output", dir.to_str().unwrap(), "--from", "2026-10-01"].iter().copied());
+        let harvested = list_records(&endpoint, &opts, &mut session).unwrap();
+        assert_eq!(harvested, Harvested { saved: 2, deleted: 1 });
+
+        let requests = api.requests();
+        assert!(requests[0].ends_with("?verb=ListRecords&metadataPrefix=oai_dc&from=2026-10-01"));
+        assert!(requests[1].ends_with("?verb=ListRecords&resumptionToken=page2"));
+        let saved = fs::read_to_string(dir.join("oai%3Afinna.fi%3Asatakirjastot.1234.xml")).unwrap();
+        assert!(saved.starts_with("<oai_dc:dc "));
+        assert!(saved.contains("<dc:title>Kissa &amp; koira: ystävykset</dc:title>"));
+        assert!(dir.join("oai%3Afinna.fi%3Amuseovirasto.ABC%2F123.xml").exists());
+        assert!(!dir.join("oai%3Afinna.fi%3Avaski.5678.xml").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
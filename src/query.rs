@@ -0,0 +1,141 @@
+//! Query strings of API requests and site links. Every key and value is
+//! percent-encoded, so that search terms keep their meaning, including Solr
+//! syntax such as phrases (`"…"`), wildcards (`*`, `?`), `+`/`-` and `&&`.
+
+use std::fmt;
+
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+
+use crate::{vec2str, Params};
+
+/// Characters left as is in values: the unreserved characters of RFC 3986.
+const VALUE: &AsciiSet = &NON_ALPHANUMERIC.remove(b'-').remove(b'_').remove(b'.').remove(b'~');
+/// Brackets are also left as is in keys, for readable `filter[0]` and `field[]`.
+const KEY: &AsciiSet = &VALUE.remove(b'[').remove(b']');
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Query {
+    pairs: Vec<(String, String)>,
+}
+
+impl Query {
+    pub fn push(&mut self, key: &str, value: &str) -> &mut Self {
+        self.pairs.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    /// Add the values of a list as `key[0]`, `key[1]` etc.
+    pub fn push_list<T: fmt::Display>(&mut self, key: &str, values: &[T]) -> &mut Self {
+        for (i, value) in values.iter().enumerate() {
+            self.push(&format!("{}[{}]", key, i), &value.to_string());
+        }
+        self
+    }
+}
+
+impl fmt::Display for Query {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, (key, value)) in self.pairs.iter().enumerate() {
+            if i > 0 {
+                f.write_str("&")?;
+            }
+            write!(f, "{}={}", utf8_percent_encode(key, KEY), utf8_percent_encode(value, VALUE))?;
+        }
+        Ok(())
+    }
+}
+
+/// Query of a search, for the API and for the search page of the site.
+pub fn search_query(params: &Params) -> Query {
+    let mut query = Query::default();
+    query
+        .push("lookfor", &vec2str(&params.lookfor, " "))
+        .push("type", &params.r#type)
+        .push_list("filter", params.filter.as_deref().unwrap_or_default());
+    if let Some(sort) = &params.sort {
+        query.push("sort", sort);
+    }
+    query
+        .push("limit", &params.limit.to_string())
+        .push("page", &params.page.to_string())
+        .push("lng", params.lng())
+        .push_list("field", &params.field);
+    query
+}
+
+/// Query of records by id.
+pub fn record_query(ids: &[String], fields: &[String]) -> Query {
+    let mut query = Query::default();
+    query.push_list("id", ids).push_list("field", fields);
+    query
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::links::{self, SiteLink};
+    use structopt::StructOpt;
+
+    fn params(args: &[&str]) -> Params {
+        Params::from_iter(std::iter::once("finna").chain(args.iter().copied()))
+    }
+
+    /// Parse the search page link of a query.
+    fn site_params(params: &Params) -> Params {
+        let link = format!("https://finna.fi/Search/Results?{}", search_query(params));
+        match links::parse(&link) {
+            Some(SiteLink::Search(params)) => *params,
+            _ => panic!("Not a search link: {}", link),
+        }
+    }
+
+    #[test]
+    fn encode() {
+        let mut query = Query::default();
+        query.push("lookfor", "kissa & koira #1 + 50% \"ä\"").push_list("filter[]", &["a"]);
+        assert_eq!(
+            query.to_string(),
+            "lookfor=kissa%20%26%20koira%20%231%20%2B%2050%25%20%22%C3%A4%22&filter[][0]=a"
+        );
+    }
+
+    #[test]
+    fn search() {
+        let mut params = params(&["--filter=-format:0/Image/", "--sort", "main_date_str desc", "--", "kissa", "koira"]);
+        params.field = vec!["id".to_string(), "title".to_string()];
+        assert_eq!(
+            search_query(&params).to_string(),
+            "lookfor=kissa%20koira&type=AllFields&filter[0]=-format%3A%220%2FImage%2F%22\
+             &sort=main_date_str%20desc&limit=20&page=1&lng=fi&field[0]=id&field[1]=title"
+        );
+    }
+
+    #[test]
+    fn records() {
+        assert_eq!(
+            record_query(&["a.1".to_string(), "b/2#x".to_string()], &["id".to_string()]).to_string(),
+            "id[0]=a.1&id[1]=b%2F2%23x&field[0]=id"
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        for lookfor in [
+            "kissa & koira",
+            "\"kissan päivät\" AND hiiri",
+            "kis* ?iro +pakollinen -pois",
+            "a=b&c=d#e",
+            "(Åbo OR Turku) && 100%",
+            "",
+        ] {
+            let mut params = params(&["--type", "Subject", "--sort", "title", "--page", "3", "--limit", "50"]);
+            params.lookfor = vec![lookfor.to_string()];
+            params.filter = Some(vec![
+                "~building:0/Tampere & Co/".parse().unwrap(),
+                "-format:\"0/Book/\"".parse().unwrap(),
+            ]);
+            params.lng = Some("sv".to_string());
+            assert_eq!(site_params(&params), params, "{}", lookfor);
+        }
+    }
+}
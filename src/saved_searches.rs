@@ -0,0 +1,394 @@
+//! Saved searches and checking them for records added since the previous check.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use chrono::{DateTime, Local};
+use structopt::StructOpt;
+
+use crate::i18n::t;
+use crate::layout::terminal_width;
+use crate::theme::paint;
+use crate::views::View;
+use crate::{error, fetch_pages, fetch_results, network_session, storage, vec2str, AppConfig, Params, Record, Session};
+
+const STORAGE: &str = "saved_searches";
+
+/// Largest page size accepted by the API.
+pub const MAX_LIMIT: i32 = 100;
+const DEFAULT_MAX_RECORDS: usize = 1000;
+/// Newest records first, so that the records added since the previous check
+/// are within the first `max_records` whatever the sort of the search.
+const CHECK_SORT: &str = "first_indexed desc";
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct SavedSearches {
+    #[serde(default)]
+    searches: BTreeMap<String, SavedSearch>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SavedSearch {
+    params: Params,
+    api_url: String,
+    site_url: String,
+    /// Ids of the records returned by the previous check
+    #[serde(default)]
+    seen: BTreeSet<String>,
+    #[serde(default)]
+    last_checked: Option<String>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum CheckFormat {
+    Text,
+    Mbox,
+    Json,
+}
+impl FromStr for CheckFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(CheckFormat::Text),
+            "mbox" => Ok(CheckFormat::Mbox),
+            "json" => Ok(CheckFormat::Json),
+            _ => Err(tr!("Unknown format: {} (expected text, mbox or json)", s)),
+        }
+    }
+}
+
+/// Re-run saved searches and list the records added since the previous check
+#[derive(StructOpt, Debug)]
+pub struct CheckOpts {
+    /// Saved searches to check (default: all)
+    names: Vec<String>,
+    /// Write the new records to a file instead of stdout (mbox files are appended to)
+    #[structopt(long, short, parse(from_os_str))]
+    output: Option<PathBuf>,
+    /// Output format: text, mbox or json (JSON Feed)
+    #[structopt(long, short, default_value = "text")]
+    format: CheckFormat,
+    /// Maximum number of records to compare per search
+    #[structopt(long, default_value = "1000")]
+    max_records: usize,
+}
+
+struct NewRecords {
+    name: String,
+    site_url: String,
+    records: Vec<Record>,
+}
+
+fn session_for(search: &SavedSearch, app_config: &AppConfig) -> Result<Session, String> {
+    network_session(&AppConfig {
+        api_url: search.api_url.clone(),
+        site_url: search.site_url.clone(),
+        ..app_config.clone()
+    })
+}
+
+/// Fetch up to `max_records` of the newest records of a saved search. The
+/// first page tells how many pages there are, the rest are fetched concurrently.
+fn fetch_records(search: &SavedSearch, max_records: usize, session: &mut Session) -> Option<Vec<Record>> {
+    let mut params = search.params.clone();
+    params.limit = MAX_LIMIT;
+    params.page = 1;
+    params.sort = Some(CHECK_SORT.to_string());
+
+    let first = fetch_results(params.clone(), session)?;
+    let total = max_records.min(first.result_count.max(0) as usize);
+    let pages = total.div_ceil(MAX_LIMIT as usize) as i32;
+    let mut records = first.records;
+    if pages > 1 {
+        for results in fetch_pages(&params, 2..=pages, session)? {
+            records.extend(results.records);
+        }
+    }
+    records.truncate(max_records);
+    Some(records)
+}
+
+fn record_ids(records: &[Record]) -> BTreeSet<String> {
+    records.iter().filter_map(|rec| rec.id.clone()).collect()
+}
+
+fn load() -> Option<SavedSearches> {
+    match storage::load(STORAGE) {
+        Ok(saved) => Some(saved),
+        Err(e) => {
+            error(&tr!("Error loading saved searches: {}", e));
+            None
+        }
+    }
+}
+
+/// Save the current search. Its current records are marked as seen so that
+/// the first check reports only records added after this.
+pub fn save(name: &str, params: &Params, session: &Session) {
+    let mut saved = match load() {
+        Some(saved) => saved,
+        None => return,
+    };
+    let mut search = SavedSearch {
+        params: params.clone(),
+        api_url: session.app_config.api_url.clone(),
+        site_url: session.app_config.site_url.clone(),
+        seen: BTreeSet::new(),
+        last_checked: None,
+    };
+    let mut check_session = match session_for(&search, &session.app_config) {
+        Ok(session) => session,
+        Err(e) => {
+            error(&e);
+            return;
+        }
+    };
+    match fetch_records(&search, DEFAULT_MAX_RECORDS, &mut check_session) {
+        Some(records) => {
+            search.seen = record_ids(&records);
+            search.last_checked = Some(Local::now().to_rfc3339());
+        }
+        None => return,
+    }
+    let count = search.seen.len();
+    saved.searches.insert(name.to_string(), search);
+    match storage::store(STORAGE, &saved) {
+        Ok(()) => println!("{}", tr!("Saved search {} ({} records seen)", paint("name", name), count)),
+        Err(e) => error(&tr!("Error saving search: {}", e)),
+    }
+}
+
+pub fn check(opts: &CheckOpts, app_config: &AppConfig) {
+    let mut saved = match load() {
+        Some(saved) => saved,
+        None => return,
+    };
+    let names: Vec<String> = if opts.names.is_empty() {
+        saved.searches.keys().cloned().collect()
+    } else {
+        opts.names.clone()
+    };
+
+    let mut found = vec![];
+    for name in names {
+        let search = match saved.searches.get_mut(&name) {
+            Some(search) => search,
+            None => {
+                error(&tr!("Unknown saved search: {}", name));
+                continue;
+            }
+        };
+        let mut session = match session_for(search, app_config) {
+            Ok(session) => session,
+            Err(e) => {
+                error(&e);
+                continue;
+            }
+        };
+        let records = match fetch_records(search, opts.max_records, &mut session) {
+            Some(records) => records,
+            None => continue,
+        };
+        let ids = record_ids(&records);
+        let new: Vec<Record> = records
+            .into_iter()
+            .filter(|rec| rec.id.as_ref().is_some_and(|id| !search.seen.contains(id)))
+            .collect();
+        // Records that have dropped out of the newest ones can't come back
+        search.seen = ids;
+        search.last_checked = Some(Local::now().to_rfc3339());
+        found.push(NewRecords {
+            name,
+            site_url: search.site_url.clone(),
+            records: new,
+        });
+    }
+
+    if opts.output.is_some() || opts.format != CheckFormat::Text {
+        colored::control::set_override(false);
+    }
+    let view = View::load(&app_config.view, app_config);
+    let written = match &opts.output {
+        Some(path) => OpenOptions::new()
+            .write(true)
+            .create(true)
+            .append(opts.format == CheckFormat::Mbox)
+            .truncate(opts.format != CheckFormat::Mbox)
+            .open(path)
+            .and_then(|mut file| write_records(&found, &opts.format, &view, &mut file)),
+        None => write_records(&found, &opts.format, &view, &mut io::stdout()),
+    };
+
+    // Only mark the records as seen once they have been reported
+    match written {
+        Ok(()) => {
+            if let Err(e) = storage::store(STORAGE, &saved) {
+                error(&tr!("Error saving searches: {}", e));
+            }
+        }
+        Err(e) => error(&tr!("Error writing output: {}", e)),
+    }
+}
+
+fn write_records(found: &[NewRecords], format: &CheckFormat, view: &View, out: &mut dyn Write) -> io::Result<()> {
+    let now = Local::now();
+    match format {
+        CheckFormat::Text => {
+            for new in found {
+                writeln!(out, "{}", tr!("{}: {} new records", paint("name", &new.name), new.records.len()))?;
+                for (i, rec) in new.records.iter().enumerate() {
+                    writeln!(out, "{}", view.format(rec, i, terminal_width(), false))?;
+                }
+            }
+        }
+        CheckFormat::Mbox => {
+            for new in found {
+                for rec in &new.records {
+                    write_mbox_message(new, rec, &now, out)?;
+                }
+            }
+        }
+        CheckFormat::Json => {
+            let items: Vec<serde_json::Value> = found
+                .iter()
+                .flat_map(|new| {
+                    new.records.iter().map(move |rec| {
+                        let url = record_url(new, rec);
+                        serde_json::json!({
+                            "id": url,
+                            "url": url,
+                            "title": rec.title.clone().unwrap_or_default(),
+                            "content_text": record_summary(rec),
+                            "date_published": now.to_rfc3339(),
+                            "tags": [new.name],
+                        })
+                    })
+                })
+                .collect();
+            let feed = serde_json::json!({
+                "version": "https://jsonfeed.org/version/1.1",
+                "title": "finna-term saved searches",
+                "items": items,
+            });
+            writeln!(out, "{}", serde_json::to_string_pretty(&feed)?)?;
+        }
+    }
+    Ok(())
+}
+
+fn record_url(new: &NewRecords, rec: &Record) -> String {
+    format!("{}/Record/{}", new.site_url, rec.id.clone().unwrap_or_default())
+}
+
+fn record_summary(rec: &Record) -> String {
+    let mut authors = rec.primary_authors.clone();
+    authors.extend(rec.non_presenter_authors.iter().map(|author| author.name.clone()));
+    let translated = |values: &[crate::TranslatedString]| -> Vec<String> {
+        values.iter().map(|value| value.translated.clone()).collect()
+    };
+    [
+        (t("Authors"), vec2str(&authors, " | ")),
+        (t("Year"), rec.year.clone().unwrap_or_default()),
+        (t("Format"), vec2str(&translated(&rec.formats), " | ")),
+        (t("Building"), vec2str(&translated(&rec.buildings), " | ")),
+    ]
+    .iter()
+    .filter(|(_, value)| !value.is_empty())
+    .map(|(label, value)| format!("{}: {}", label, value))
+    .collect::<Vec<String>>()
+    .join("\n")
+}
+
+/// Header value on one line, as RFC 2047 encoded words unless it's ASCII.
+fn encode_header(value: &str) -> String {
+    let value = value.split_whitespace().collect::<Vec<&str>>().join(" ");
+    if value.is_ascii() {
+        return value;
+    }
+    // 45 bytes of UTF-8 keep an encoded word within the 75 characters allowed
+    let mut chunks = vec![String::new()];
+    for c in value.chars() {
+        if chunks.last().unwrap().len() + c.len_utf8() > 45 {
+            chunks.push(String::new());
+        }
+        chunks.last_mut().unwrap().push(c);
+    }
+    chunks
+        .iter()
+        .map(|chunk| format!("=?utf-8?B?{}?=", base64::encode(chunk)))
+        .collect::<Vec<String>>()
+        .join("\n ")
+}
+
+fn write_mbox_message(
+    new: &NewRecords,
+    rec: &Record,
+    now: &DateTime<Local>,
+    out: &mut dyn Write,
+) -> io::Result<()> {
+    writeln!(out, "From finna-term {}", now.format("%a %b %e %H:%M:%S %Y"))?;
+    writeln!(out, "From: finna-term <finna-term@localhost>")?;
+    writeln!(out, "Date: {}", now.to_rfc2822())?;
+    let subject = format!("[{}] {}", new.name, rec.title.clone().unwrap_or_default());
+    writeln!(out, "Subject: {}", encode_header(&subject))?;
+    writeln!(out, "MIME-Version: 1.0")?;
+    writeln!(out, "Content-Type: text/plain; charset=utf-8")?;
+    writeln!(out)?;
+    let body = format!("{}\n\n{}", record_summary(rec), record_url(new, rec));
+    for line in body.lines() {
+        // mboxrd quoting
+        if line.trim_start_matches('>').starts_with("From ") {
+            write!(out, ">")?;
+        }
+        writeln!(out, "{}", line)?;
+    }
+    writeln!(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http;
+    use crate::mock_api::MockApi;
+
+    #[test]
+    fn header() {
+        assert_eq!(encode_header("[kissat] Cats\nand dogs"), "[kissat] Cats and dogs");
+        assert_eq!(encode_header("[kissat] Kissa ja äiti"), "=?utf-8?B?W2tpc3NhdF0gS2lzc2EgamEgw6RpdGk=?=");
+        let long = encode_header(&"ä".repeat(30));
+        let words: Vec<&str> = long.split("\n ").collect();
+        assert_eq!(words.len(), 2);
+        assert!(words.iter().all(|word| word.len() <= 75));
+        let decoded: Vec<u8> = words
+            .iter()
+            .flat_map(|word| base64::decode(&word[10..word.len() - 2]).unwrap())
+            .collect();
+        assert_eq!(String::from_utf8(decoded).unwrap(), "ä".repeat(30));
+    }
+
+    #[test]
+    fn newest_records() {
+        let api = MockApi::start();
+        let search = SavedSearch {
+            params: Params::from_iter(["finna", "kissa", "--sort", "title"].iter().copied()),
+            api_url: api.url.clone(),
+            site_url: String::new(),
+            seen: BTreeSet::new(),
+            last_checked: None,
+        };
+        let app_config = AppConfig {
+            api_url: api.url.clone(),
+            ..AppConfig::default()
+        };
+        let mut session = Session::new(app_config, api.client(&http::HttpConfig::default()));
+        let records = fetch_records(&search, 10, &mut session).unwrap();
+        assert!(records.len() <= 10);
+        let requests = api.requests();
+        assert!(requests[0].contains("sort=first_indexed%20desc"), "{}", requests[0]);
+    }
+}
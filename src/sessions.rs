@@ -0,0 +1,153 @@
+//! Saving the state of a session so that it can be resumed later.
+
+use std::collections::BTreeMap;
+
+use chrono::Local;
+
+use crate::history::SearchHistory;
+use crate::layout::Layout;
+use crate::theme::paint;
+use crate::{error, format_search_summary, storage, view_results, Params, Record, SearchResults, Session};
+
+const STORAGE: &str = "sessions";
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct SavedSessions {
+    #[serde(default)]
+    sessions: BTreeMap<String, SavedSession>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SavedSession {
+    params: Params,
+    results: SearchResults,
+    api_url: String,
+    site_url: String,
+    last_search: Option<String>,
+    #[serde(default)]
+    history: SearchHistory,
+    #[serde(default)]
+    pinned: Vec<Record>,
+    #[serde(default)]
+    view: String,
+    #[serde(default)]
+    layout: Option<Layout>,
+    saved: String,
+}
+
+fn load_sessions() -> Option<SavedSessions> {
+    match storage::load(STORAGE) {
+        Ok(saved) => Some(saved),
+        Err(e) => {
+            error(&tr!("Error loading sessions: {}", e));
+            None
+        }
+    }
+}
+
+/// Save the session under the given name. The session is saved again under
+/// the same name when the program exits.
+pub fn save(name: &str, params: &Params, results: &SearchResults, session: &mut Session) {
+    let mut saved = match load_sessions() {
+        Some(saved) => saved,
+        None => return,
+    };
+    saved.sessions.insert(
+        name.to_string(),
+        SavedSession {
+            params: params.clone(),
+            results: results.clone(),
+            api_url: session.app_config.api_url.clone(),
+            site_url: session.app_config.site_url.clone(),
+            last_search: session.last_search.clone(),
+            history: session.history.clone(),
+            pinned: session.pinned.clone(),
+            view: session.view.clone(),
+            layout: session.layout,
+            saved: Local::now().to_rfc3339(),
+        },
+    );
+    match storage::store(STORAGE, &saved) {
+        Ok(()) => {
+            session.name = Some(name.to_string());
+            println!("{}", tr!("Saved session {}", paint("name", name)));
+        }
+        Err(e) => error(&tr!("Error saving session: {}", e)),
+    }
+}
+
+/// Restore a saved session and show its current results.
+pub fn load(name: &str, session: &mut Session) -> Option<(Params, SearchResults)> {
+    let mut saved = load_sessions()?;
+    let restored = match saved.sessions.remove(name) {
+        Some(restored) => restored,
+        None => {
+            error(&tr!("Unknown session: {}", name));
+            return None;
+        }
+    };
+    session.name = Some(name.to_string());
+    session.app_config.api_url = restored.api_url;
+    session.app_config.site_url = restored.site_url;
+    session.last_search = restored.last_search;
+    session.history = restored.history;
+    session.pinned = restored.pinned;
+    session.view = restored.view;
+    session.layout = restored.layout;
+
+    view_results(&restored.params, &restored.results, session);
+    println!("{}", tr!("Restored session {} (saved {})", paint("name", name), restored.saved));
+    Some((restored.params, restored.results))
+}
+
+/// Restore the session saved under `name`, or start a new session that is
+/// saved under the name on exit.
+pub fn start(name: &str, session: &mut Session) -> Option<(Params, SearchResults)> {
+    if load_sessions()?.sessions.contains_key(name) {
+        return load(name, session);
+    }
+    session.name = Some(name.to_string());
+    println!("{}", tr!("New session {}, saved on exit", paint("name", name)));
+    None
+}
+
+pub fn list() {
+    if let Some(saved) = load_sessions() {
+        for (name, saved_session) in &saved.sessions {
+            println!(
+                "{name}  {saved}  {search}",
+                name = paint("name", name),
+                saved = paint("date", &saved_session.saved),
+                search = format_search_summary(&saved_session.params, &saved_session.results)
+            );
+        }
+    }
+}
+
+pub fn pin(rec: &Record, session: &mut Session) {
+    if !session.pinned.iter().any(|pinned| pinned.id == rec.id) {
+        session.pinned.push(rec.clone());
+    }
+    println!("{}", tr!("{} pinned records", session.pinned.len()));
+}
+
+pub fn unpin(rec: &Record, session: &mut Session) {
+    session.pinned.retain(|pinned| pinned.id != rec.id);
+    println!("{}", tr!("{} pinned records", session.pinned.len()));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{mock_api, AppConfig};
+
+    #[test]
+    fn start_new() {
+        colored::control::set_override(false);
+        let mut session = Session::new(AppConfig::default(), mock_api::offline_client());
+        let name = format!("new-{}", std::process::id());
+        assert!(start(&name, &mut session).is_none());
+        assert_eq!(session.name, Some(name));
+    }
+}
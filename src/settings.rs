@@ -0,0 +1,178 @@
+//! Changing the parameters of the current search with `:set` and `:filter`.
+
+use crate::filters::{Filter, Operator};
+use crate::i18n::{self, t};
+use crate::saved_searches::MAX_LIMIT;
+use crate::{vec2str, Params, Session};
+
+fn parse_number(value: &str, max: i32) -> Option<i32> {
+    value.parse().ok().filter(|number| (1..=max).contains(number))
+}
+
+/// Switch the language of search results and of the user interface.
+pub fn set_lng(code: &str, params: &mut Params, session: &mut Session) -> Result<(), String> {
+    let lang = code.parse::<i18n::Lang>()?;
+    i18n::set(lang);
+    if session.app_config.ui_lng.is_some() {
+        session.app_config.ui_lng = Some(code.to_string());
+    }
+    params.lng = Some(lang.api_code().to_string());
+    Ok(())
+}
+
+/// Set a search parameter, e.g. `limit 50`. Changes other than the page start
+/// from the first page, except `limit` which keeps the first result of the page in view.
+pub fn set(arg: &str, params: &mut Params, session: &mut Session) -> Result<(), String> {
+    let (key, value) = arg
+        .split_once(' ')
+        .map(|(key, value)| (key, value.trim()))
+        .ok_or_else(|| t("Usage: :set limit|page|type|sort|lng <value>").to_string())?;
+    match key {
+        "limit" => {
+            let limit = parse_number(value, MAX_LIMIT).ok_or_else(|| tr!("Invalid limit: {} (expected 1-{})", value, MAX_LIMIT))?;
+            params.page = (params.page - 1) * params.limit / limit + 1;
+            params.limit = limit;
+        }
+        "page" => {
+            params.page = parse_number(value, i32::MAX).ok_or_else(|| tr!("Invalid page: {}", value))?;
+        }
+        "type" => {
+            params.r#type = value.to_string();
+            params.page = 1;
+        }
+        "sort" => {
+            params.sort = Some(value.to_string());
+            params.page = 1;
+        }
+        "lng" => set_lng(value, params, session)?,
+        _ => return Err(tr!("Unknown parameter: {}", key)),
+    }
+    Ok(())
+}
+
+/// Add (`add <filter>`, `not <filter>` to exclude, `or <filter>` to OR with
+/// filters of the same field), remove (`rm <num>` or `rm <filter>`) or clear (`clear`) filters.
+pub fn filter(arg: &str, params: &mut Params) -> Result<(), String> {
+    let (action, value) = arg.split_once(' ').map_or((arg, ""), |(action, value)| (action, value.trim()));
+    let mut filters = params.filter.take().unwrap_or_default();
+    let result = match action {
+        "add" | "not" | "or" if !value.is_empty() => value.parse::<Filter>().map(|mut filter| {
+            match action {
+                "not" => filter.operator = Operator::Not,
+                "or" => filter.operator = Operator::Or,
+                _ => {}
+            }
+            if !filters.contains(&filter) {
+                filters.push(filter);
+            }
+        }),
+        "rm" => {
+            let index = match value.parse::<usize>() {
+                Ok(num) => num.checked_sub(1).filter(|&i| i < filters.len()),
+                Err(_) => value.parse::<Filter>().ok().and_then(|removed| filters.iter().position(|filter| *filter == removed)),
+            };
+            match index {
+                Some(index) => {
+                    filters.remove(index);
+                    Ok(())
+                }
+                None => Err(tr!("No such filter: {}", value)),
+            }
+        }
+        "clear" => {
+            filters.clear();
+            Ok(())
+        }
+        _ => Err(t("Usage: :filter add|not|or <filter>, :filter rm <num>, :filter clear").to_string()),
+    };
+    if result.is_ok() {
+        params.page = 1;
+    }
+    if !filters.is_empty() {
+        params.filter = Some(filters);
+    }
+    result
+}
+
+/// Numbered list of the filters, for `:filter rm`.
+pub fn format_filters(params: &Params) -> String {
+    params
+        .filter
+        .iter()
+        .flatten()
+        .enumerate()
+        .map(|(i, filter)| format!("{:>3} {}", i + 1, filter))
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+/// Parameters of the current search.
+pub fn format_params(params: &Params) -> String {
+    let mut lines: Vec<String> = [
+        ("lookfor", vec2str(&params.lookfor, " ")),
+        ("type", params.r#type.clone()),
+        ("sort", params.sort.clone().unwrap_or_default()),
+        ("limit", params.limit.to_string()),
+        ("page", params.page.to_string()),
+        ("lng", params.lng().to_string()),
+    ]
+    .iter()
+    .map(|(key, value)| format!("{:<8} {}", key, value).trim_end().to_string())
+    .collect();
+    if params.filter.is_some() {
+        lines.push("filter".to_string());
+        lines.push(format_filters(params));
+    }
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{mock_api, AppConfig};
+    use structopt::StructOpt;
+
+    fn params(args: &[&str]) -> Params {
+        Params::from_iter(std::iter::once("finna").chain(args.iter().copied()))
+    }
+
+    #[test]
+    fn set_limit_keeps_position() {
+        let mut session = Session::new(AppConfig::default(), mock_api::offline_client());
+        let mut params = params(&["--page", "3", "kissa"]);
+        set("limit 10", &mut params, &mut session).unwrap();
+        // The first result of page 3 with 20 per page is number 41, which is on page 5
+        assert_eq!((params.limit, params.page), (10, 5));
+        set("limit 100", &mut params, &mut session).unwrap();
+        assert_eq!((params.limit, params.page), (100, 1));
+        assert!(set("limit 0", &mut params, &mut session).is_err());
+        assert!(set("page x", &mut params, &mut session).is_err());
+    }
+
+    #[test]
+    fn set_resets_page() {
+        let mut session = Session::new(AppConfig::default(), mock_api::offline_client());
+        let mut params = params(&["--page", "3", "kissa"]);
+        set("sort year", &mut params, &mut session).unwrap();
+        assert_eq!((params.sort.as_deref(), params.page), (Some("year"), 1));
+    }
+
+    #[test]
+    fn add_and_remove_filters() {
+        let mut params = params(&["--page", "2", "kissa"]);
+        filter("add format:0/Book/", &mut params).unwrap();
+        filter("not format:0/Image/", &mut params).unwrap();
+        filter("or building:0/Helka/", &mut params).unwrap();
+        assert_eq!(params.page, 1);
+        assert_eq!(
+            format_filters(&params),
+            "  1 format:\"0/Book/\"\n  2 -format:\"0/Image/\"\n  3 ~building:\"0/Helka/\""
+        );
+        filter("rm 1", &mut params).unwrap();
+        filter("rm ~building:0/Helka/", &mut params).unwrap();
+        assert_eq!(format_filters(&params), "  1 -format:\"0/Image/\"");
+        assert!(filter("rm 5", &mut params).is_err());
+        filter("clear", &mut params).unwrap();
+        assert!(params.filter.is_none());
+    }
+}
@@ -0,0 +1,41 @@
+//! Local data files (saved searches etc.), stored as JSON in the user's data directory.
+
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use directories::ProjectDirs;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+fn data_dir() -> PathBuf {
+    // Tests don't touch the user's data
+    if cfg!(test) {
+        return std::env::temp_dir().join(format!("finna-term-test-{}", std::process::id()));
+    }
+    ProjectDirs::from("rs", "finna-term", "finna-term")
+        .data_dir()
+        .to_path_buf()
+}
+
+fn path(name: &str) -> PathBuf {
+    data_dir().join(format!("{}.json", name))
+}
+
+/// Load a data file, or the default value if it doesn't exist yet.
+pub fn load<T: DeserializeOwned + Default>(name: &str) -> io::Result<T> {
+    match fs::read_to_string(path(name)) {
+        Ok(data) => Ok(serde_json::from_str(&data)?),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(T::default()),
+        Err(e) => Err(e),
+    }
+}
+
+pub fn store<T: Serialize>(name: &str, data: &T) -> io::Result<()> {
+    fs::create_dir_all(data_dir())?;
+    let target = path(name);
+    // Write a temporary file first so that an interrupted write doesn't truncate the data
+    let tmp = target.with_extension("json.tmp");
+    fs::write(&tmp, serde_json::to_string_pretty(data)?)?;
+    fs::rename(tmp, target)
+}
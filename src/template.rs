@@ -0,0 +1,227 @@
+//! Templates for result lines.
+//!
+//! `{name}` is replaced with the value of a field. Modifiers follow the name:
+//! `{name:N}` truncates to N columns, `{name:<N}` and `{name:>N}` pad or
+//! truncate to exactly N columns, aligned left or right, and `|style`
+//! (e.g. `{title|bold}`, `{format|yellow|bold}`) sets colours and styles.
+//! A style can also name a role of the colour theme, e.g. `{title|title}`.
+//! `*` as the width (`{title:*}`) takes the space left over on the line.
+//! Text in `[...]` is left out when any field inside it is empty, e.g. `[ ({year})]`.
+//! `\` escapes the next character.
+
+use colored::*;
+
+use crate::layout::{pad, truncate, visible_width};
+use crate::theme;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Align {
+    None,
+    Left,
+    Right,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Width {
+    Fixed(usize),
+    /// Rest of the line
+    Fill,
+}
+
+#[derive(Debug, Clone)]
+struct Placeholder {
+    name: String,
+    align: Align,
+    width: Option<Width>,
+    styles: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+enum Part {
+    Literal(String),
+    Field(Placeholder),
+    Optional(Vec<Part>),
+}
+
+#[derive(Debug, Clone)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+fn parse_placeholder(spec: &str) -> Result<Placeholder, String> {
+    let mut styles = spec.split('|');
+    let field = styles.next().unwrap_or("");
+    let (name, width) = match field.split_once(':') {
+        Some((name, width)) => (name, Some(width)),
+        None => (field, None),
+    };
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(tr!("Missing field name in {}", format!("{{{}}}", spec)));
+    }
+    let (align, width) = match width {
+        Some(width) => {
+            let (align, digits) = if let Some(digits) = width.strip_prefix('<') {
+                (Align::Left, digits)
+            } else if let Some(digits) = width.strip_prefix('>') {
+                (Align::Right, digits)
+            } else {
+                (Align::None, width)
+            };
+            let width = match digits.trim() {
+                "*" => Width::Fill,
+                digits => Width::Fixed(
+                    digits
+                        .parse::<usize>()
+                        .map_err(|_| tr!("Invalid width in {}", format!("{{{}}}", spec)))?,
+                ),
+            };
+            (align, Some(width))
+        }
+        None => (Align::None, None),
+    };
+    Ok(Placeholder {
+        name: name.to_string(),
+        align,
+        width,
+        styles: styles.map(|style| style.trim().to_string()).collect(),
+    })
+}
+
+fn parse_parts(chars: &mut std::str::Chars, in_group: bool) -> Result<Vec<Part>, String> {
+    let mut parts = vec![];
+    let mut literal = String::new();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => literal.extend(chars.next()),
+            '{' => {
+                let mut spec = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => spec.push(c),
+                        None => return Err(tr!("Unclosed {}", format!("{{{}", spec))),
+                    }
+                }
+                if !literal.is_empty() {
+                    parts.push(Part::Literal(std::mem::take(&mut literal)));
+                }
+                parts.push(Part::Field(parse_placeholder(&spec)?));
+            }
+            '[' => {
+                if !literal.is_empty() {
+                    parts.push(Part::Literal(std::mem::take(&mut literal)));
+                }
+                parts.push(Part::Optional(parse_parts(chars, true)?));
+            }
+            ']' if in_group => {
+                if !literal.is_empty() {
+                    parts.push(Part::Literal(literal));
+                }
+                return Ok(parts);
+            }
+            c => literal.push(c),
+        }
+    }
+    if in_group {
+        return Err(tr!("Unclosed {}", "["));
+    }
+    if !literal.is_empty() {
+        parts.push(Part::Literal(literal));
+    }
+    Ok(parts)
+}
+
+impl Placeholder {
+    /// Render a value, `fill_width` being the width of `*` fields.
+    fn render(&self, value: &str, fill_width: usize) -> String {
+        let width = match self.width {
+            Some(Width::Fixed(width)) => Some(width),
+            Some(Width::Fill) => Some(fill_width),
+            None => None,
+        };
+        let text = match width {
+            Some(width) => {
+                let text = truncate(value, width);
+                match self.align {
+                    Align::Left => pad(&text, width, false),
+                    Align::Right => pad(&text, width, true),
+                    Align::None => text,
+                }
+            }
+            None => value.to_string(),
+        };
+        self.styles
+            .iter()
+            .fold(text.as_str().normal(), |text, style| theme::style(text, style))
+            .to_string()
+    }
+}
+
+/// Render parts, returning None if a field of an optional group is empty.
+/// `*` fields are left empty if `fill_width` is None.
+fn render_parts(
+    parts: &[Part],
+    lookup: &dyn Fn(&str) -> String,
+    optional: bool,
+    fill_width: Option<usize>,
+) -> Option<String> {
+    let mut out = String::new();
+    for part in parts {
+        match part {
+            Part::Literal(text) => out.push_str(text),
+            Part::Field(placeholder) => {
+                let value = lookup(&placeholder.name);
+                if optional && value.is_empty() {
+                    return None;
+                }
+                match (&placeholder.width, fill_width) {
+                    (Some(Width::Fill), None) => {}
+                    (_, fill_width) => out.push_str(&placeholder.render(&value, fill_width.unwrap_or(0))),
+                }
+            }
+            Part::Optional(parts) => {
+                out.push_str(&render_parts(parts, lookup, true, fill_width).unwrap_or_default());
+            }
+        }
+    }
+    Some(out)
+}
+
+fn count_fill_fields(parts: &[Part]) -> usize {
+    parts
+        .iter()
+        .map(|part| match part {
+            Part::Field(placeholder) if placeholder.width == Some(Width::Fill) => 1,
+            Part::Optional(parts) => count_fill_fields(parts),
+            _ => 0,
+        })
+        .sum()
+}
+
+impl Template {
+    pub fn parse(template: &str) -> Result<Template, String> {
+        Ok(Template {
+            parts: parse_parts(&mut template.chars(), false)?,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.parts.is_empty()
+    }
+
+    /// Render the template for a line of `line_width` columns, looking up
+    /// field values with `lookup`.
+    pub fn render(&self, lookup: &dyn Fn(&str) -> String, line_width: usize) -> String {
+        let fill_fields = count_fill_fields(&self.parts);
+        let fill_width = match fill_fields {
+            0 => 0,
+            fill_fields => {
+                // Share the space not taken by the rest of the line
+                let rest = render_parts(&self.parts, lookup, false, None).unwrap_or_default();
+                line_width.saturating_sub(visible_width(&rest)) / fill_fields
+            }
+        };
+        render_parts(&self.parts, lookup, false, Some(fill_width)).unwrap_or_default()
+    }
+}
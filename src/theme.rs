@@ -0,0 +1,156 @@
+//! Colour themes and colour output settings.
+//!
+//! A theme maps the roles of text in the output (e.g. `title`, `error`) to
+//! space separated styles, e.g. `title = "bold underline"`. Themes are defined
+//! in `AppConfig`; roles missing from a configured theme use the default styles.
+
+use std::collections::HashMap;
+use std::io::{self, IsTerminal};
+use std::str::FromStr;
+use std::sync::RwLock;
+
+use colored::*;
+
+use crate::AppConfig;
+
+pub const DEFAULT_THEME: &str = "default";
+
+/// Roles with their styles in the built-in `default` and `mono` themes.
+const ROLES: &[(&str, &str, &str)] = &[
+    ("count", "yellow", ""),
+    ("title", "bold", "bold"),
+    ("format", "yellow", ""),
+    ("building", "blue", "italic"),
+    ("lookfor", "yellow bold", "bold"),
+    ("name", "yellow bold", "bold"),
+    ("label", "yellow", "bold"),
+    ("heading", "bold", "bold underline"),
+    ("link", "blue underline", "underline"),
+    ("marker", "green", "bold"),
+    ("tags", "cyan", ""),
+    ("notes", "dimmed", "dimmed"),
+    ("date", "dimmed", "dimmed"),
+    ("debug", "dimmed", "dimmed"),
+    ("error", "red bold", "bold"),
+    ("prompt", "green bold", "bold"),
+    ("hint", "bold", "dimmed"),
+];
+
+pub type Theme = HashMap<String, String>;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ColorMode {
+    /// Colours when writing to a terminal, unless `NO_COLOR` is set
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+impl FromStr for ColorMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(ColorMode::Auto),
+            "always" => Ok(ColorMode::Always),
+            "never" => Ok(ColorMode::Never),
+            _ => Err(tr!("Unknown colour mode: {} (expected auto, always or never)", s)),
+        }
+    }
+}
+
+static THEME: RwLock<Option<Theme>> = RwLock::new(None);
+
+pub fn default_themes() -> HashMap<String, Theme> {
+    let default = ROLES.iter().map(|role| (role.0.to_string(), role.1.to_string())).collect();
+    let mono = ROLES.iter().map(|role| (role.0.to_string(), role.2.to_string())).collect();
+    let mut themes = HashMap::new();
+    themes.insert(DEFAULT_THEME.to_string(), default);
+    themes.insert("mono".to_string(), mono);
+    themes
+}
+
+pub fn names(app_config: &AppConfig) -> Vec<String> {
+    let mut names: Vec<String> = app_config.themes.keys().cloned().collect();
+    names.extend(default_themes().into_keys());
+    names.sort();
+    names.dedup();
+    names
+}
+
+/// Select the theme used by `paint`.
+pub fn set(name: &str, app_config: &AppConfig) -> Result<(), String> {
+    let overrides = app_config
+        .themes
+        .get(name)
+        .cloned()
+        .or_else(|| default_themes().remove(name))
+        .ok_or_else(|| tr!("Unknown theme: {}", name))?;
+    let mut theme = default_themes().remove(DEFAULT_THEME).unwrap_or_default();
+    theme.extend(overrides);
+    *THEME.write().unwrap() = Some(theme);
+    Ok(())
+}
+
+/// Set up colours and the theme from the configuration. Screen reader mode
+/// turns colours off.
+pub fn init(app_config: &AppConfig) {
+    match app_config.color {
+        _ if app_config.screen_reader => colored::control::set_override(false),
+        ColorMode::Always => colored::control::set_override(true),
+        ColorMode::Never => colored::control::set_override(false),
+        // colored follows NO_COLOR, CLICOLOR(_FORCE) and whether stdout is a terminal
+        ColorMode::Auto => {}
+    }
+    if let Err(e) = set(&app_config.theme, app_config) {
+        crate::error(&tr!("{} (using the default theme)", e));
+    }
+}
+
+/// Styles of a role in the current theme.
+fn role_styles(role: &str) -> Option<String> {
+    match THEME.read().unwrap().as_ref() {
+        Some(theme) => theme.get(role).cloned(),
+        None => ROLES.iter().find(|r| r.0 == role).map(|r| r.1.to_string()),
+    }
+}
+
+fn apply_style(text: ColoredString, style: &str) -> ColoredString {
+    match style {
+        "" => text,
+        "bold" => text.bold(),
+        "dimmed" => text.dimmed(),
+        "italic" => text.italic(),
+        "underline" => text.underline(),
+        "reversed" => text.reversed(),
+        "strikethrough" => text.strikethrough(),
+        style => match style.strip_prefix("on_") {
+            Some(color) => text.on_color(color),
+            None => text.color(style),
+        },
+    }
+}
+
+/// Style text with the styles of a theme role, or with the style itself if
+/// it is not a role (e.g. `bold` or `red`).
+pub fn style(text: ColoredString, style: &str) -> ColoredString {
+    match role_styles(style) {
+        Some(styles) => styles.split_whitespace().fold(text, apply_style),
+        None => apply_style(text, style),
+    }
+}
+
+/// Style text written to stdout with the styles of a theme role.
+pub fn paint(role: &str, text: &str) -> ColoredString {
+    style(text.normal(), role)
+}
+
+/// Style text written to stderr, which is left plain unless it is a terminal.
+pub fn paint_stderr(role: &str, text: &str) -> String {
+    if io::stderr().is_terminal() {
+        paint(role, text).to_string()
+    } else {
+        text.to_string()
+    }
+}
@@ -0,0 +1,153 @@
+//! Distribution of search results over time, from the year facet.
+
+use std::collections::{BTreeMap, HashMap};
+
+use crate::filters::{Filter, Operator};
+use crate::i18n::t;
+use crate::layout::pad;
+use crate::theme::paint;
+use crate::query::search_query;
+use crate::{call_api, parse_response, Params, Session};
+
+const YEAR_FIELD: &str = "main_date_str";
+/// Years are grouped into decades or centuries to keep the chart at most this tall.
+const MAX_BARS: i64 = 30;
+/// Years asked for in the facet, so that old and rare years aren't left out.
+const FACET_LIMIT: u32 = 1000;
+const EIGHTHS: [&str; 8] = ["", "▏", "▎", "▍", "▌", "▋", "▊", "▉"];
+
+#[derive(Debug, Deserialize)]
+struct FacetValue {
+    value: String,
+    #[serde(default)]
+    count: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct FacetResults {
+    #[serde(default)]
+    facets: HashMap<String, Vec<FacetValue>>,
+}
+
+pub struct Bar {
+    pub first: i64,
+    pub last: i64,
+    pub count: u64,
+}
+
+impl Bar {
+    fn label(&self) -> String {
+        if self.first == self.last {
+            self.first.to_string()
+        } else {
+            format!("{}–{}", self.first, self.last)
+        }
+    }
+}
+
+/// Fetch the number of results by year for a search.
+fn fetch_years(params: &Params, session: &mut Session) -> Option<BTreeMap<i64, u64>> {
+    let mut params = params.clone();
+    params.limit = 0;
+    params.page = 1;
+    params.field = vec![];
+    let mut query = search_query(&params);
+    query.push("facet[]", YEAR_FIELD);
+    query.push("facetLimit", &FACET_LIMIT.to_string());
+    let url = format!("{}/search?{}", session.app_config.api_url, query);
+    let results: FacetResults = parse_response(&call_api(&url, session)?)?;
+    Some(
+        results
+            .facets
+            .get(YEAR_FIELD)
+            .into_iter()
+            .flatten()
+            .filter_map(|facet| Some((facet.value.trim().parse::<i64>().ok()?, facet.count)))
+            .collect(),
+    )
+}
+
+/// Group years into bars of one, ten or a hundred years, including empty ones
+/// between the first and the last year.
+fn group(years: &BTreeMap<i64, u64>) -> Vec<Bar> {
+    let (first, last) = match (years.keys().next(), years.keys().next_back()) {
+        (Some(first), Some(last)) => (*first, *last),
+        _ => return vec![],
+    };
+    let size = [1, 10, 100, 1000]
+        .iter()
+        .copied()
+        .find(|size| last.div_euclid(*size) - first.div_euclid(*size) < MAX_BARS)
+        .unwrap_or(1000);
+    (first.div_euclid(size)..=last.div_euclid(size))
+        .map(|bucket| {
+            let (start, end) = (bucket * size, bucket * size + size - 1);
+            Bar {
+                first: start,
+                last: end,
+                count: years.range(start..=end).map(|(_, count)| count).sum(),
+            }
+        })
+        .collect()
+}
+
+pub fn fetch_bars(params: &Params, session: &mut Session) -> Option<Vec<Bar>> {
+    fetch_years(params, session).map(|years| group(&years))
+}
+
+/// Numbered horizontal bars fitted to `width` columns, or plain numbers if `plain` is set.
+pub fn format_chart(bars: &[Bar], width: usize, plain: bool) -> String {
+    if bars.is_empty() {
+        return t("No years in the results").to_string();
+    }
+    let max = bars.iter().map(|bar| bar.count).max().unwrap_or(0).max(1);
+    let label_width = bars.iter().map(|bar| bar.label().chars().count()).max().unwrap_or(0);
+    let count_width = max.to_string().len();
+    let bar_width = width.saturating_sub(label_width + count_width + 7).max(10);
+    bars.iter()
+        .enumerate()
+        .map(|(i, bar)| {
+            if plain {
+                return format!("{}. {}: {}", i + 1, bar.label(), bar.count);
+            }
+            let eighths = (bar.count * bar_width as u64 * 8 / max) as usize;
+            let bar_text = "█".repeat(eighths / 8) + EIGHTHS[eighths % 8];
+            format!(
+                "{num:>3} {label} {bar} {count}",
+                num = paint("count", &(i + 1).to_string()),
+                label = pad(&bar.label(), label_width, true),
+                bar = paint("format", &bar_text),
+                count = bar.count
+            )
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+/// Limit the search to the years of a bar, e.g. `main_date_str:"[1950 TO 1959]"`,
+/// replacing any earlier year filter.
+pub fn apply_bar(bar: &Bar, params: &mut Params) -> Result<(), String> {
+    if bar.count == 0 {
+        return Err(tr!("No results in {}", bar.label()));
+    }
+    let mut filters: Vec<Filter> = params
+        .filter
+        .take()
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|filter| filter.field != YEAR_FIELD)
+        .collect();
+    let value = if bar.first == bar.last {
+        bar.first.to_string()
+    } else {
+        format!("[{} TO {}]", bar.first, bar.last)
+    };
+    filters.push(Filter {
+        operator: Operator::And,
+        field: YEAR_FIELD.to_string(),
+        value,
+    });
+    params.filter = Some(filters);
+    params.page = 1;
+    Ok(())
+}
@@ -0,0 +1,157 @@
+//! Field sets and result line templates configured in `AppConfig`.
+
+use std::collections::HashMap;
+
+use serde_json::Value;
+
+use crate::template::Template;
+use crate::{error, rec_fields, vec2str, AppConfig, Author, Record};
+
+pub const DEFAULT_VIEW: &str = "default";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ViewConfig {
+    /// Name of the field set to fetch
+    pub fields: String,
+    pub line1: String,
+    #[serde(default)]
+    pub line2: String,
+}
+
+pub fn default_field_sets() -> HashMap<String, Vec<String>> {
+    let mut field_sets = HashMap::new();
+    field_sets.insert("brief".to_string(), rec_fields());
+    field_sets
+}
+
+pub fn default_views() -> HashMap<String, ViewConfig> {
+    let mut views = HashMap::new();
+    views.insert(
+        DEFAULT_VIEW.to_string(),
+        ViewConfig {
+            fields: "brief".to_string(),
+            line1: "{count:>3|count} {title:*|title}[ ({year})]  {format|format} - {format_code}".to_string(),
+            line2: "    {authors}  {building|building}".to_string(),
+        },
+    );
+    views
+}
+
+pub struct View {
+    pub fields: Vec<String>,
+    line1: Template,
+    line2: Template,
+}
+
+impl View {
+    fn from_config(name: &str, app_config: &AppConfig) -> Result<View, String> {
+        // Built-in views and field sets are available unless overridden in the config
+        let config = app_config
+            .views
+            .get(name)
+            .cloned()
+            .or_else(|| default_views().remove(name))
+            .ok_or_else(|| tr!("Unknown view: {}", name))?;
+        let mut fields = app_config
+            .field_sets
+            .get(&config.fields)
+            .cloned()
+            .or_else(|| default_field_sets().remove(&config.fields))
+            .ok_or_else(|| tr!("Unknown field set: {}", config.fields))?;
+        // Record actions need the id
+        if !fields.iter().any(|field| field == "id") {
+            fields.insert(0, "id".to_string());
+        }
+        Ok(View {
+            fields,
+            line1: Template::parse(&config.line1)?,
+            line2: Template::parse(&config.line2)?,
+        })
+    }
+
+    /// Load a view, falling back to the built-in default view if it is invalid.
+    pub fn load(name: &str, app_config: &AppConfig) -> View {
+        View::from_config(name, app_config).unwrap_or_else(|e| {
+            error(&tr!("{} (using the default view)", e));
+            let config = &default_views()[DEFAULT_VIEW];
+            View {
+                fields: rec_fields(),
+                line1: Template::parse(&config.line1).unwrap(),
+                line2: Template::parse(&config.line2).unwrap(),
+            }
+        })
+    }
+
+    pub fn names(app_config: &AppConfig) -> Vec<String> {
+        let mut names: Vec<String> = app_config.views.keys().cloned().collect();
+        names.extend(default_views().into_keys());
+        names.sort();
+        names.dedup();
+        names
+    }
+
+    pub fn exists(name: &str, app_config: &AppConfig) -> Result<(), String> {
+        View::from_config(name, app_config).map(|_| ())
+    }
+
+    /// Format a search result to fit `width` columns, `cnt` being its index on the page.
+    /// `single_line` leaves out the second line.
+    pub fn format(&self, rec: &Record, cnt: usize, width: usize, single_line: bool) -> String {
+        let data = serde_json::to_value(rec).unwrap_or(Value::Null);
+        let lookup = |name: &str| field_value(rec, &data, name, cnt);
+        let mut lines = vec![self.line1.render(&lookup, width)];
+        if !self.line2.is_empty() && !single_line {
+            lines.push(self.line2.render(&lookup, width));
+        }
+        lines.join("\n")
+    }
+}
+
+pub fn authors(rec: &Record) -> Vec<String> {
+    let authors_to_str = |authors: &Vec<Author>| -> Vec<String> {
+        authors.iter().map(|p| p.name.clone()).collect()
+    };
+    if !rec.primary_authors.is_empty() {
+        rec.primary_authors.clone()
+    } else {
+        authors_to_str(&rec.non_presenter_authors)
+    }
+}
+
+/// Value of a template field: one of the computed fields below or any record
+/// field by its API name (`extra.` prefix is optional), `.` separating nested keys.
+fn field_value(rec: &Record, data: &Value, name: &str, cnt: usize) -> String {
+    match name {
+        "count" => (cnt + 1).to_string(),
+        "format" => rec.formats.last().map_or("?".to_string(), |format| format.translated.clone()),
+        "format_code" => rec.formats.last().map_or("?".to_string(), |format| format.value.clone()),
+        "building" => rec.buildings.first().map(|building| building.translated.clone()).unwrap_or_default(),
+        "authors" => vec2str(&authors(rec), " | "),
+        _ => {
+            let path = name.strip_prefix("extra.").unwrap_or(name);
+            path.split('.')
+                .try_fold(data, |value, key| value.get(key))
+                .map(|value| value_to_string(value, 0))
+                .unwrap_or_default()
+        }
+    }
+}
+
+fn value_to_string(value: &Value, depth: usize) -> String {
+    match value {
+        Value::Null => "".to_string(),
+        Value::String(s) => s.clone(),
+        Value::Array(values) => values
+            .iter()
+            .map(|value| value_to_string(value, depth + 1))
+            .filter(|s| !s.is_empty())
+            .collect::<Vec<String>>()
+            .join(if depth == 0 { ", " } else { " : " }),
+        Value::Object(fields) => ["translated", "name", "value"]
+            .iter()
+            .find_map(|key| fields.get(*key))
+            .map(|value| value_to_string(value, depth))
+            .unwrap_or_else(|| value.to_string()),
+        value => value.to_string(),
+    }
+}